@@ -0,0 +1,108 @@
+//! With the `precompiled-shaders` feature, compiles every shader under
+//! `shaders/` to SPIR-V and embeds it through `$OUT_DIR/shaders.rs`.
+
+use std::{env, fs, path::PathBuf};
+
+#[cfg(feature = "precompiled-shaders")]
+#[path = "src/compile.rs"]
+mod compile;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    #[cfg(feature = "precompiled-shaders")]
+    let entries = precompile(&out_dir);
+    #[cfg(not(feature = "precompiled-shaders"))]
+    let entries = String::new();
+
+    let source = format!(
+        "pub static PRECOMPILED_SHADERS: &[(&str, &str, &[u8])] = &[\n{}];\n",
+        entries
+    );
+    fs::write(out_dir.join("shaders.rs"), source).unwrap();
+}
+
+#[cfg(feature = "precompiled-shaders")]
+fn precompile(out_dir: &std::path::Path) -> String {
+    use compile::{compile, stage_extension, Language};
+    use naga::ShaderStage;
+    use std::path::Path;
+
+    fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+        for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                walk(&path, files);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    let root = Path::new("shaders");
+    println!("cargo:rerun-if-changed=src/compile.rs");
+    println!("cargo:rerun-if-changed={}", root.display());
+
+    let mut files = vec![];
+    walk(root, &mut files);
+    files.sort();
+
+    let mut entries = String::new();
+    for path in files {
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let Some(language) = compile::Language::from_path(&path) else {
+            continue;
+        };
+
+        let source = fs::read_to_string(&path).unwrap();
+        let stages = match (language, path.extension().and_then(|e| e.to_str())) {
+            (Language::Wgsl, _) => naga::front::wgsl::parse_str(&source)
+                .map(|m| m.entry_points.iter().map(|e| e.stage).collect())
+                .unwrap_or_else(|_| vec![ShaderStage::Vertex]),
+            (Language::Glsl, Some("vert")) => vec![ShaderStage::Vertex],
+            (Language::Glsl, Some("frag")) => vec![ShaderStage::Fragment],
+            (Language::Glsl, Some("comp")) => vec![ShaderStage::Compute],
+            _ => {
+                println!(
+                    "cargo:warning=Skipping `{}`, its stage is unknown.",
+                    path.display()
+                );
+                continue;
+            }
+        };
+
+        let name = path
+            .strip_prefix(root)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        for stage in stages {
+            let stage_name = stage_extension(stage);
+
+            let words = compile(&path, &source, language, stage).unwrap_or_else(|diagnostics| {
+                for diagnostic in &diagnostics {
+                    println!("cargo:warning={}", diagnostic);
+                }
+                panic!("Failed to compile `{}`.", path.display());
+            });
+
+            let output = out_dir.join(format!("{}.{}.spv", name.replace('/', "_"), stage_name));
+            let bytes = words
+                .iter()
+                .flat_map(|w| w.to_le_bytes())
+                .collect::<Vec<_>>();
+            fs::write(&output, bytes).unwrap();
+
+            entries.push_str(&format!(
+                "    ({:?}, {:?}, include_bytes!({:?})),\n",
+                name, stage_name, output
+            ));
+        }
+    }
+
+    entries
+}
@@ -0,0 +1,727 @@
+//! Sub-allocates device memory from large blocks, so the number of
+//! `vkAllocateMemory` calls stays far below `maxMemoryAllocationCount`.
+
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use std::{
+    collections::HashMap,
+    fmt,
+    ptr::NonNull,
+    sync::{Arc, Mutex},
+};
+use vulkanalia::{
+    prelude::v1_0::*,
+    vk::{
+        Buffer, BufferCreateInfo, BufferUsageFlags, DeviceMemory, DeviceSize, Image, ImageTiling,
+        MemoryAllocateInfo, MemoryMapFlags, MemoryPropertyFlags, MemoryRequirements,
+        PhysicalDevice, PhysicalDeviceMemoryProperties, SharingMode, WHOLE_SIZE,
+    },
+};
+
+/// The size of the blocks allocations are carved from.
+pub const DEFAULT_BLOCK_SIZE: DeviceSize = 64 * 1024 * 1024;
+
+/// Heaps up to this size use blocks of an eighth of the heap instead, so a
+/// small heap (e.g. the host-visible part of VRAM) is not used up by one
+/// block.
+const SMALL_HEAP_SIZE: DeviceSize = 1024 * 1024 * 1024;
+
+/// Who accesses the memory of an allocation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MemoryLocation {
+    /// Only accessed by the device.
+    GpuOnly,
+    /// Written by the host and read by the device, e.g. uploads.
+    CpuToGpu,
+    /// Written by the device and read by the host, e.g. captures.
+    GpuToCpu,
+}
+
+impl MemoryLocation {
+    fn required_flags(self) -> MemoryPropertyFlags {
+        match self {
+            Self::GpuOnly => MemoryPropertyFlags::empty(),
+            Self::CpuToGpu | Self::GpuToCpu => {
+                MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT
+            }
+        }
+    }
+
+    fn preferred_flags(self) -> MemoryPropertyFlags {
+        match self {
+            Self::GpuOnly | Self::CpuToGpu => MemoryPropertyFlags::DEVICE_LOCAL,
+            Self::GpuToCpu => MemoryPropertyFlags::HOST_CACHED,
+        }
+    }
+}
+
+/// How a block hands out its memory.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Strategy {
+    /// Bumps an offset. Freed space is only reclaimed once the whole block is
+    /// empty, which suits short-lived allocations.
+    Linear,
+    /// First fit over a list of free ranges that are merged again when
+    /// allocations are freed, which suits long-lived allocations.
+    FreeList,
+}
+
+/// A range of device memory handed out by an `Allocator`.
+///
+/// It has to be returned with `Allocator::free`, otherwise it is reported
+/// as leaked when the allocator is destroyed. It can't be cloned, so it is
+/// freed at most once.
+#[derive(Debug)]
+pub struct Allocation {
+    pub memory: DeviceMemory,
+    pub offset: DeviceSize,
+    pub size: DeviceSize,
+    mapped: Option<NonNull<u8>>,
+    id: u64,
+    block: usize,
+    /// Where the allocation starts in the block, including alignment.
+    start: DeviceSize,
+}
+
+// The mapped pointer points into a block that stays mapped until the
+// allocation is freed.
+unsafe impl Send for Allocation {}
+
+impl Allocation {
+    /// The host address of the allocation if its memory is host visible.
+    /// Host-visible blocks stay mapped for as long as they exist.
+    pub fn mapped_ptr(&self) -> Option<NonNull<u8>> {
+        self.mapped
+    }
+
+    /// The contents of the allocation if its memory is host visible.
+    ///
+    /// # Safety
+    ///
+    /// The device must not be writing to the memory.
+    pub unsafe fn mapped_slice(&self) -> Option<&[u8]> {
+        self.mapped
+            .map(|p| std::slice::from_raw_parts(p.as_ptr(), self.size as usize))
+    }
+}
+
+/// Memory usage of an `Allocator`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AllocatorStats {
+    pub blocks: usize,
+    pub allocations: usize,
+    /// The size of every block.
+    pub bytes_allocated: DeviceSize,
+    /// The size of every live allocation.
+    pub bytes_used: DeviceSize,
+    /// Alignment padding and freed space linear blocks can't reuse yet.
+    pub bytes_wasted: DeviceSize,
+}
+
+impl fmt::Display for AllocatorStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} allocations in {} blocks, {} of {} bytes used, {} bytes wasted",
+            self.allocations, self.blocks, self.bytes_used, self.bytes_allocated, self.bytes_wasted
+        )
+    }
+}
+
+/// One `vkAllocateMemory` allocation that allocations are carved from.
+///
+/// Blocks only hold buffers and linear images or only optimal images, so
+/// neighbouring allocations never have to respect
+/// `bufferImageGranularity`.
+#[derive(Debug)]
+struct Block {
+    memory: DeviceMemory,
+    memory_type: u32,
+    size: DeviceSize,
+    strategy: Strategy,
+    tiling: ImageTiling,
+    /// Whether the block holds a single allocation that didn't fit a
+    /// regular block.
+    dedicated: bool,
+    mapped: Option<NonNull<u8>>,
+    allocations: usize,
+    used: DeviceSize,
+    /// The next free offset of a linear block.
+    top: DeviceSize,
+    /// The free ranges of a free-list block, sorted by offset.
+    free: Vec<(DeviceSize, DeviceSize)>,
+    /// The alignment padding in a free-list block.
+    padding: DeviceSize,
+}
+
+// The mapped pointer is only dereferenced through the allocations handed
+// out for it, never by the block itself.
+unsafe impl Send for Block {}
+
+impl Block {
+    /// Reserves `size` bytes aligned to `alignment`, returning where the
+    /// reserved range starts and the aligned offset.
+    fn reserve(
+        &mut self,
+        size: DeviceSize,
+        alignment: DeviceSize,
+    ) -> Option<(DeviceSize, DeviceSize)> {
+        let (start, offset) = match self.strategy {
+            Strategy::Linear => {
+                let start = self.top;
+                let offset = start.next_multiple_of(alignment);
+                if offset + size > self.size {
+                    return None;
+                }
+                self.top = offset + size;
+                (start, offset)
+            }
+            Strategy::FreeList => {
+                let (index, start, offset) =
+                    self.free
+                        .iter()
+                        .enumerate()
+                        .find_map(|(i, &(start, len))| {
+                            let offset = start.next_multiple_of(alignment);
+                            (offset + size <= start + len).then_some((i, start, offset))
+                        })?;
+
+                let (free_start, free_len) = self.free[index];
+                let end = offset + size;
+                if end == free_start + free_len {
+                    self.free.remove(index);
+                } else {
+                    self.free[index] = (end, free_start + free_len - end);
+                }
+                self.padding += offset - start;
+                (start, offset)
+            }
+        };
+
+        self.allocations += 1;
+        self.used += size;
+        Some((start, offset))
+    }
+
+    fn release(&mut self, allocation: &Allocation) {
+        self.allocations -= 1;
+        self.used -= allocation.size;
+
+        match self.strategy {
+            Strategy::Linear => {
+                if self.allocations == 0 {
+                    self.top = 0;
+                }
+            }
+            Strategy::FreeList => {
+                let end = allocation.offset + allocation.size;
+                self.padding -= allocation.offset - allocation.start;
+
+                let index = self.free.partition_point(|&(o, _)| o < allocation.start);
+                self.free
+                    .insert(index, (allocation.start, end - allocation.start));
+
+                if index + 1 < self.free.len() && self.free[index + 1].0 == end {
+                    self.free[index].1 += self.free.remove(index + 1).1;
+                }
+                if index > 0 && self.free[index - 1].0 + self.free[index - 1].1 == allocation.start
+                {
+                    self.free[index - 1].1 += self.free.remove(index).1;
+                }
+            }
+        }
+    }
+
+    fn wasted(&self) -> DeviceSize {
+        match self.strategy {
+            Strategy::Linear => self.top - self.used,
+            Strategy::FreeList => self.padding,
+        }
+    }
+
+    unsafe fn destroy(&self, device: &Device) {
+        if self.mapped.is_some() {
+            device.unmap_memory(self.memory);
+        }
+        device.free_memory(self.memory, None);
+    }
+}
+
+#[derive(Debug, Default)]
+struct AllocatorState {
+    memory_properties: PhysicalDeviceMemoryProperties,
+    block_size: DeviceSize,
+    /// Freed blocks leave an empty slot so the indices of the others stay
+    /// valid.
+    blocks: Vec<Option<Block>>,
+    /// The name and size of every live allocation, for leak reports.
+    live: HashMap<u64, (String, DeviceSize)>,
+    next_id: u64,
+}
+
+impl AllocatorState {
+    fn find_memory_type(&self, type_bits: u32, location: MemoryLocation) -> Result<u32> {
+        let required = location.required_flags();
+        let preferred = location.preferred_flags();
+
+        // Memory types are ordered by preference, so the first of the best
+        // matches wins.
+        (0..self.memory_properties.memory_type_count)
+            .filter(|i| type_bits & (1 << i) != 0)
+            .filter(|i| {
+                let flags = self.memory_properties.memory_types[*i as usize].property_flags;
+                flags.contains(required)
+            })
+            .min_by_key(|i| {
+                let flags = self.memory_properties.memory_types[*i as usize].property_flags;
+                !(flags & preferred).bits().count_ones()
+            })
+            .ok_or_else(|| anyhow!("Failed to find a {:?} memory type.", location))
+    }
+
+    fn block_size(&self, memory_type: u32) -> DeviceSize {
+        let memory_type = self.memory_properties.memory_types[memory_type as usize];
+        let heap = self.memory_properties.memory_heaps[memory_type.heap_index as usize];
+        if heap.size <= SMALL_HEAP_SIZE {
+            self.block_size.min(heap.size / 8)
+        } else {
+            self.block_size
+        }
+    }
+
+    unsafe fn create_block(
+        &mut self,
+        device: &Device,
+        memory_type: u32,
+        size: DeviceSize,
+        strategy: Strategy,
+        tiling: ImageTiling,
+        dedicated: bool,
+    ) -> Result<usize> {
+        let info = MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type);
+        let memory = device.allocate_memory(&info, None)?;
+
+        let flags = self.memory_properties.memory_types[memory_type as usize].property_flags;
+        let mapped = if flags.contains(MemoryPropertyFlags::HOST_VISIBLE) {
+            match device.map_memory(memory, 0, WHOLE_SIZE as u64, MemoryMapFlags::empty()) {
+                Ok(pointer) => NonNull::new(pointer.cast()),
+                Err(error) => {
+                    device.free_memory(memory, None);
+                    return Err(error.into());
+                }
+            }
+        } else {
+            None
+        };
+
+        debug!(
+            "Allocated a {} byte {:?} block of memory type {}.",
+            size, strategy, memory_type
+        );
+
+        let block = Block {
+            memory,
+            memory_type,
+            size,
+            strategy,
+            tiling,
+            dedicated,
+            mapped,
+            allocations: 0,
+            used: 0,
+            top: 0,
+            free: vec![(0, size)],
+            padding: 0,
+        };
+
+        match self.blocks.iter().position(Option::is_none) {
+            Some(index) => {
+                self.blocks[index] = Some(block);
+                Ok(index)
+            }
+            None => {
+                self.blocks.push(Some(block));
+                Ok(self.blocks.len() - 1)
+            }
+        }
+    }
+}
+
+/// Hands out device memory from large blocks per memory type.
+#[derive(Clone, Debug, Default)]
+pub struct Allocator {
+    state: Arc<Mutex<AllocatorState>>,
+}
+
+impl Allocator {
+    /// # Safety
+    ///
+    /// `physical_device` must belong to `instance`.
+    pub unsafe fn new(
+        instance: &Instance,
+        physical_device: PhysicalDevice,
+        block_size: DeviceSize,
+    ) -> Self {
+        let state = AllocatorState {
+            memory_properties: instance.get_physical_device_memory_properties(physical_device),
+            block_size,
+            ..Default::default()
+        };
+
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// The memory type out of `type_bits` that best suits `location`.
+    pub fn find_memory_type(&self, type_bits: u32, location: MemoryLocation) -> Result<u32> {
+        self.state
+            .lock()
+            .unwrap()
+            .find_memory_type(type_bits, location)
+    }
+
+    /// Allocates memory for a resource with `requirements`. Buffers and
+    /// linear images use `ImageTiling::LINEAR`.
+    ///
+    /// Allocations larger than half a block get a block of their own.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be created from the physical device the allocator was
+    /// created for.
+    pub unsafe fn allocate(
+        &self,
+        device: &Device,
+        name: &str,
+        requirements: MemoryRequirements,
+        location: MemoryLocation,
+        strategy: Strategy,
+        tiling: ImageTiling,
+    ) -> Result<Allocation> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+
+        let memory_type = state.find_memory_type(requirements.memory_type_bits, location)?;
+        let block_size = state.block_size(memory_type);
+        let (size, alignment) = (requirements.size, requirements.alignment.max(1));
+
+        let existing = state.blocks.iter_mut().enumerate().find_map(|(i, block)| {
+            let block = block.as_mut()?;
+            let matches = !block.dedicated
+                && block.memory_type == memory_type
+                && block.strategy == strategy
+                && block.tiling == tiling;
+            matches.then(|| block.reserve(size, alignment).map(|r| (i, r)))?
+        });
+
+        let (index, (start, offset)) = match existing {
+            Some(reserved) => reserved,
+            None => {
+                let dedicated = size > block_size / 2;
+                let index = state.create_block(
+                    device,
+                    memory_type,
+                    if dedicated { size } else { block_size },
+                    strategy,
+                    tiling,
+                    dedicated,
+                )?;
+                let block = state.blocks[index].as_mut().unwrap();
+                let reserved = block
+                    .reserve(size, alignment)
+                    .ok_or_else(|| anyhow!("Allocation `{}` does not fit a new block.", name))?;
+                (index, reserved)
+            }
+        };
+
+        let block = state.blocks[index].as_ref().unwrap();
+        let mapped = block
+            .mapped
+            .map(|p| NonNull::new_unchecked(p.as_ptr().add(offset as usize)));
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.live.insert(id, (name.into(), size));
+
+        Ok(Allocation {
+            memory: block.memory,
+            offset,
+            size,
+            mapped,
+            id,
+            block: index,
+            start,
+        })
+    }
+
+    /// Creates a buffer and binds it to newly allocated memory.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be created from the physical device the allocator was
+    /// created for.
+    pub unsafe fn create_buffer(
+        &self,
+        device: &Device,
+        name: &str,
+        size: DeviceSize,
+        usage: BufferUsageFlags,
+        location: MemoryLocation,
+        strategy: Strategy,
+    ) -> Result<(Buffer, Allocation)> {
+        let info = BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(SharingMode::EXCLUSIVE);
+
+        let buffer = device.create_buffer(&info, None)?;
+
+        let requirements = device.get_buffer_memory_requirements(buffer);
+        let allocation = match self.allocate(
+            device,
+            name,
+            requirements,
+            location,
+            strategy,
+            ImageTiling::LINEAR,
+        ) {
+            Ok(allocation) => allocation,
+            Err(error) => {
+                device.destroy_buffer(buffer, None);
+                return Err(error);
+            }
+        };
+
+        if let Err(error) = device.bind_buffer_memory(buffer, allocation.memory, allocation.offset)
+        {
+            device.destroy_buffer(buffer, None);
+            self.free(device, allocation);
+            return Err(error.into());
+        }
+
+        Ok((buffer, allocation))
+    }
+
+    /// Allocates memory for an image with optimal tiling and binds it.
+    ///
+    /// # Safety
+    ///
+    /// `image` must belong to `device`, which must be created from the
+    /// physical device the allocator was created for. It must not be bound to
+    /// memory yet.
+    pub unsafe fn allocate_image(
+        &self,
+        device: &Device,
+        name: &str,
+        image: Image,
+        location: MemoryLocation,
+        strategy: Strategy,
+    ) -> Result<Allocation> {
+        let requirements = device.get_image_memory_requirements(image);
+        let allocation = self.allocate(
+            device,
+            name,
+            requirements,
+            location,
+            strategy,
+            ImageTiling::OPTIMAL,
+        )?;
+
+        if let Err(error) = device.bind_image_memory(image, allocation.memory, allocation.offset) {
+            self.free(device, allocation);
+            return Err(error.into());
+        }
+
+        Ok(allocation)
+    }
+
+    /// Returns an allocation to its block. Dedicated and host-visible blocks
+    /// are freed once empty, and so are other empty blocks unless they are
+    /// the last of their kind.
+    ///
+    /// # Safety
+    ///
+    /// `allocation` must come from this allocator and the device must no
+    /// longer use it.
+    pub unsafe fn free(&self, device: &Device, allocation: Allocation) {
+        let mut state = self.state.lock().unwrap();
+        state.live.remove(&allocation.id);
+
+        let block = state.blocks[allocation.block].as_mut().unwrap();
+        block.release(&allocation);
+        if block.allocations > 0 {
+            return;
+        }
+
+        // Host-visible memory is scarce on some devices, and its blocks mostly
+        // back short-lived buffers such as captures, so they are not kept.
+        let block = state.blocks[allocation.block].as_ref().unwrap();
+        let spare = block.dedicated
+            || block.mapped.is_some()
+            || state.blocks.iter().enumerate().any(|(i, b)| {
+                b.as_ref().is_some_and(|b| {
+                    i != allocation.block
+                        && !b.dedicated
+                        && b.memory_type == block.memory_type
+                        && b.strategy == block.strategy
+                        && b.tiling == block.tiling
+                })
+            });
+
+        if spare {
+            block.destroy(device);
+            state.blocks[allocation.block] = None;
+        }
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        let state = self.state.lock().unwrap();
+        state
+            .blocks
+            .iter()
+            .flatten()
+            .fold(AllocatorStats::default(), |stats, block| AllocatorStats {
+                blocks: stats.blocks + 1,
+                allocations: stats.allocations + block.allocations,
+                bytes_allocated: stats.bytes_allocated + block.size,
+                bytes_used: stats.bytes_used + block.used,
+                bytes_wasted: stats.bytes_wasted + block.wasted(),
+            })
+    }
+
+    /// Frees every block, reporting allocations that were never freed.
+    ///
+    /// # Safety
+    ///
+    /// The device must be idle. No allocation may be used afterwards.
+    pub unsafe fn destroy(&self, device: &Device) {
+        debug!("Destroying allocator: {}.", self.stats());
+
+        let mut state = self.state.lock().unwrap();
+
+        let mut leaked = state.live.drain().map(|(_, l)| l).collect::<Vec<_>>();
+        leaked.sort();
+        for (name, size) in &leaked {
+            warn!("Leaked allocation `{}` ({} bytes).", name, size);
+        }
+
+        state
+            .blocks
+            .drain(..)
+            .flatten()
+            .for_each(|b| b.destroy(device));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(strategy: Strategy) -> Block {
+        Block {
+            memory: DeviceMemory::default(),
+            memory_type: 0,
+            size: 1024,
+            strategy,
+            tiling: ImageTiling::LINEAR,
+            dedicated: false,
+            mapped: None,
+            allocations: 0,
+            used: 0,
+            top: 0,
+            free: vec![(0, 1024)],
+            padding: 0,
+        }
+    }
+
+    fn reserve(block: &mut Block, size: DeviceSize, alignment: DeviceSize) -> Allocation {
+        let (start, offset) = block.reserve(size, alignment).unwrap();
+        Allocation {
+            memory: block.memory,
+            offset,
+            size,
+            mapped: None,
+            id: 0,
+            block: 0,
+            start,
+        }
+    }
+
+    #[test]
+    fn free_list_pads_to_alignment() {
+        let mut block = block(Strategy::FreeList);
+        let a = reserve(&mut block, 10, 1);
+        let b = reserve(&mut block, 100, 64);
+
+        assert_eq!((a.start, a.offset), (0, 0));
+        assert_eq!((b.start, b.offset), (10, 64));
+        assert_eq!(block.free, vec![(164, 860)]);
+        assert_eq!(block.used, 110);
+        assert_eq!(block.wasted(), 54);
+    }
+
+    #[test]
+    fn free_list_coalesces_with_both_neighbours() {
+        let mut block = block(Strategy::FreeList);
+        let a = reserve(&mut block, 10, 1);
+        let b = reserve(&mut block, 100, 64);
+        let c = reserve(&mut block, 50, 1);
+
+        block.release(&a);
+        assert_eq!(block.free, vec![(0, 10), (214, 810)]);
+
+        block.release(&c);
+        assert_eq!(block.free, vec![(0, 10), (164, 860)]);
+
+        // Freeing `b` joins the ranges before and after it.
+        block.release(&b);
+        assert_eq!(block.free, vec![(0, 1024)]);
+        assert_eq!((block.allocations, block.used, block.wasted()), (0, 0, 0));
+    }
+
+    #[test]
+    fn free_list_reuses_freed_ranges() {
+        let mut block = block(Strategy::FreeList);
+        let a = reserve(&mut block, 512, 1);
+        let _b = reserve(&mut block, 512, 1);
+        assert!(block.reserve(1, 1).is_none());
+
+        block.release(&a);
+        let c = reserve(&mut block, 256, 256);
+        assert_eq!(c.offset, 0);
+        assert_eq!(block.free, vec![(256, 256)]);
+    }
+
+    #[test]
+    fn linear_pads_to_alignment() {
+        let mut block = block(Strategy::Linear);
+        let a = reserve(&mut block, 10, 1);
+        let b = reserve(&mut block, 10, 256);
+
+        assert_eq!((a.start, a.offset), (0, 0));
+        assert_eq!((b.start, b.offset), (10, 256));
+        assert_eq!(block.top, 266);
+        assert_eq!(block.wasted(), 246);
+        assert!(block.reserve(800, 1).is_none());
+    }
+
+    #[test]
+    fn linear_resets_once_empty() {
+        let mut block = block(Strategy::Linear);
+        let a = reserve(&mut block, 100, 1);
+        let b = reserve(&mut block, 100, 1);
+
+        // Freed space before the top is wasted until the block is empty.
+        block.release(&a);
+        assert_eq!(block.top, 200);
+        assert_eq!(block.wasted(), 100);
+
+        block.release(&b);
+        assert_eq!(block.top, 0);
+        assert_eq!((block.allocations, block.used, block.wasted()), (0, 0, 0));
+        assert_eq!(reserve(&mut block, 1024, 1).offset, 0);
+    }
+}
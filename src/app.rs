@@ -1,90 +1,734 @@
 use anyhow::{anyhow, Ok, Result};
-use log::{info, warn};
-use std::{collections::HashSet, ffi::CStr, os::raw::c_void};
+use log::{error, info, warn};
+use naga::ShaderStage;
+use std::{
+    collections::HashSet,
+    ffi::CString,
+    os::raw::c_void,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
 use vulkanalia::{
-    loader::{LibloadingLoader, LIBRARY},
     prelude::v1_0::*,
     vk::{
-        make_version, ApplicationInfo, Bool32, DebugUtilsMessageSeverityFlagsEXT,
-        DebugUtilsMessageTypeFlagsEXT, DebugUtilsMessengerCallbackDataEXT,
-        DebugUtilsMessengerCreateInfoEXT, DebugUtilsMessengerEXT, ExtDebugUtilsExtension,
-        ExtensionName, InstanceCreateFlags, InstanceCreateInfo, EXT_DEBUG_UTILS_EXTENSION, FALSE,
-        KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION, KHR_PORTABILITY_ENUMERATION_EXTENSION,
+        make_version, AccessFlags, ApplicationInfo, AttachmentDescription, AttachmentLoadOp,
+        AttachmentReference, AttachmentStoreOp, Buffer, BufferImageCopy, BufferMemoryBarrier,
+        BufferUsageFlags, ClearColorValue, ClearValue, ColorSpaceKHR, CommandBuffer,
+        CommandBufferBeginInfo, CommandBufferUsageFlags, CompositeAlphaFlagsKHR,
+        DebugUtilsMessengerCreateInfoEXT, DebugUtilsMessengerCreateInfoEXTBuilder,
+        DebugUtilsMessengerEXT, DependencyFlags, DeviceCreateInfo, DeviceQueueCreateInfo,
+        DeviceSize, ExtDebugUtilsExtension, ExtensionName, Extent2D, Extent3D, Fence,
+        FenceCreateFlags, FenceCreateInfo, Format, Framebuffer, FramebufferCreateInfo, Image,
+        ImageAspectFlags, ImageCreateInfo, ImageLayout, ImageMemoryBarrier, ImageSubresourceLayers,
+        ImageSubresourceRange, ImageTiling, ImageType, ImageUsageFlags, ImageView,
+        ImageViewCreateInfo, ImageViewType, InstanceCreateFlags, InstanceCreateInfo,
+        KhrSurfaceExtension, KhrSwapchainExtension, MemoryBarrier, Offset2D, Offset3D,
+        PhysicalDevice, PhysicalDeviceFeatures, PhysicalDeviceType, PipelineBindPoint,
+        PipelineCache, PipelineStageFlags, PresentInfoKHR, PresentModeKHR, Queue, QueueFlags,
+        Rect2D, RenderPass, RenderPassBeginInfo, RenderPassCreateInfo, SampleCountFlags, Semaphore,
+        SemaphoreCreateInfo, SharingMode, SubmitInfo, SubpassContents, SubpassDependency,
+        SubpassDescription, SuccessCode, SurfaceCapabilitiesKHR, SurfaceFormatKHR, SurfaceKHR,
+        SwapchainCreateInfoKHR, SwapchainKHR, ValidationFeatureEnableEXT, ValidationFeaturesEXT,
+        Viewport, EXT_DEBUG_UTILS_EXTENSION, KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION,
+        KHR_PORTABILITY_ENUMERATION_EXTENSION, KHR_PORTABILITY_SUBSET_EXTENSION,
+        KHR_SWAPCHAIN_EXTENSION, QUEUE_FAMILY_IGNORED, SUBPASS_EXTERNAL, WHOLE_SIZE,
     },
-    window::get_required_instance_extensions,
+    window::{create_surface, get_required_instance_extensions},
     Instance, Version,
 };
 use winit::window::Window;
 
-const PORTABILITY_MACOS_VERSION: Version = Version::new(1, 3, 126);
+use crate::allocator::{Allocation, Allocator, MemoryLocation, Strategy, DEFAULT_BLOCK_SIZE};
+use crate::capture;
+use crate::commands::CommandContext;
+use crate::config::{AppConfig, ValidationConfig, ValidationErrorPolicy};
+use crate::error::AppError;
+use crate::loader;
+use crate::pipeline::{GraphicsPipeline, PipelineBuilder, ShaderSource};
+use crate::pipeline_cache;
+use crate::requirements::{InstanceRequirements, NegotiatedInstance};
+use crate::validation::{debug_callback, ValidationMessage, ValidationSink};
+use crate::watch::ShaderWatcher;
 
-const VALIDATION_ENABLED: bool = cfg!(debug_assertions);
-const VALIDATION_LAYER: ExtensionName = ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation");
+const MIN_DEVICE_API_VERSION: Version = Version::V1_0_0;
+const DEVICE_EXTENSIONS: &[ExtensionName] = &[KHR_SWAPCHAIN_EXTENSION.name];
 
-extern "system" fn debug_callback(
-    severity: DebugUtilsMessageSeverityFlagsEXT,
-    type_: DebugUtilsMessageTypeFlagsEXT,
-    data: *const DebugUtilsMessengerCallbackDataEXT,
-    _: *mut c_void,
-) -> Bool32 {
-    let data = unsafe { *data };
-    let message = unsafe { CStr::from_ptr(data.message) }.to_string_lossy();
-
-    if severity >= DebugUtilsMessageSeverityFlagsEXT::ERROR {
-        panic!("({:?}) {}", type_, message);
-    } else if severity >= DebugUtilsMessageSeverityFlagsEXT::WARNING {
-        warn!("({:?}) {}", type_, message);
-    } else if severity >= DebugUtilsMessageSeverityFlagsEXT::INFO {
-        dbg!("({:?}) {}", type_, message);
-    } else {
-        println!("({:?}) {}", type_, message);
-    };
+const OFFSCREEN_FORMAT: Format = Format::R8G8B8A8_UNORM;
 
-    FALSE
-}
+const VALIDATION_LAYER: ExtensionName = ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation");
+// Deprecated in favor of `VK_EXT_layer_settings`, but supported by every
+// validation layer release that has these features.
+const VALIDATION_FEATURES_EXTENSION: ExtensionName =
+    ExtensionName::from_bytes(b"VK_EXT_validation_features");
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct App {
     instance: Instance,
     data: AppData,
+    // Never read, but keeps the Vulkan loader library loaded.
+    #[allow(dead_code)]
     entry: Entry,
+    device: Device,
+    frame: usize,
+    resized: bool,
 }
 
 impl App {
-    pub unsafe fn create(window: &Window) -> Result<Self> {
-        let loader = LibloadingLoader::new(LIBRARY)?;
-        let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
-        let mut data = AppData::default();
+    /// # Safety
+    ///
+    /// `window` must outlive the app, and `destroy` must be called before the
+    /// app is dropped.
+    pub unsafe fn create(window: &Window, config: &AppConfig) -> Result<Self, AppError> {
+        Self::new(Some(window), config).map_err(AppError::from)
+    }
+
+    /// Creates an app without a surface that renders into an offscreen
+    /// color image instead of a swapchain.
+    ///
+    /// # Safety
+    ///
+    /// `destroy` must be called before the app is dropped.
+    pub unsafe fn create_headless(config: &AppConfig) -> Result<Self, AppError> {
+        Self::new(None, config).map_err(AppError::from)
+    }
+
+    unsafe fn new(window: Option<&Window>, config: &AppConfig) -> Result<Self> {
+        config.apply_icd_override();
+        let entry = loader::load_entry(config.loader_path.as_deref())?;
+        let mut data = AppData {
+            headless: window.is_none(),
+            max_api_version: config.api_version,
+            validation: config.validation.clone(),
+            validation_sink: Arc::new(ValidationSink::new(config.validation.muted_ids.clone())),
+            clear_color: config.clear_color,
+            surface_formats: config.surface_formats.clone(),
+            present_modes: config.present_modes.clone(),
+            max_frames_in_flight: config.frames_in_flight,
+            shaders: config
+                .vertex_shader
+                .clone()
+                .zip(config.fragment_shader.clone()),
+            pipeline_cache_path: config.pipeline_cache.clone(),
+            ..Default::default()
+        };
         let instance = create_instance(window, &entry, &mut data)?;
 
+        if let Some(window) = window {
+            data.surface = create_surface(&instance, window, window)?;
+        }
+
+        pick_physical_device(&instance, config, &mut data)?;
+        let device = create_logical_device(&instance, &mut data)?;
+        create_allocator(&instance, &mut data);
+        create_pipeline_cache(&instance, &device, &mut data)?;
+
+        if let Some(window) = window {
+            create_swapchain(window, &instance, &device, &mut data)?;
+            create_swapchain_image_views(&device, &mut data)?;
+        } else {
+            let (width, height) = config.size;
+            data.color_format = OFFSCREEN_FORMAT;
+            data.extent = Extent2D { width, height };
+            create_offscreen_target(&device, &mut data)?;
+        }
+
+        create_render_pass(&device, &mut data)?;
+        data.shader_code = data.shaders.as_ref().map(load_shader_code).transpose()?;
+        create_pipeline(&device, &mut data)?;
+        create_framebuffers(&device, &mut data)?;
+
+        create_command_context(&instance, &device, &mut data)?;
+
+        if config.watch_shaders {
+            create_shader_watcher(&mut data);
+        }
+        create_sync_objects(&device, &mut data)?;
+
         Ok(Self {
             entry,
             data,
             instance,
+            device,
+            frame: 0,
+            resized: false,
         })
     }
 
-    pub unsafe fn render(&mut self, window: &Window) -> Result<()> {
+    /// Tells the app that the window was resized, so the swapchain is
+    /// recreated before the next frame.
+    pub fn window_resized(&mut self) {
+        self.resized = true;
+    }
+
+    /// Renders a frame. Nothing is rendered while the window is minimized.
+    ///
+    /// # Safety
+    ///
+    /// `window` must be the window the app was created for, and the app must
+    /// not have been destroyed.
+    pub unsafe fn render(&mut self, window: &Window) -> Result<(), AppError> {
+        self.check_validation_errors()?;
+
+        let size = window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            return Result::Ok(());
+        }
+
+        self.reload_shaders();
+
+        if self.resized {
+            self.resized = false;
+            return self.recreate_swapchain(window).map_err(AppError::from);
+        }
+
+        match self.draw_frame().map_err(AppError::from) {
+            Result::Ok(true) => {
+                info!("Swapchain is suboptimal, recreating it.");
+                self.recreate_swapchain(window).map_err(AppError::from)
+            }
+            Result::Ok(false) => Result::Ok(()),
+            Err(AppError::OutOfDate(_)) => {
+                info!("Swapchain is out of date, recreating it.");
+                self.recreate_swapchain(window).map_err(AppError::from)
+            }
+            Err(AppError::SurfaceLost(_)) => {
+                warn!("Surface lost, recreating it.");
+                self.recreate_surface(window).map_err(AppError::from)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Returns whether the swapchain no longer matches the surface.
+    unsafe fn draw_frame(&mut self) -> Result<bool> {
+        let in_flight_fence = self.data.in_flight_fences[self.frame];
+
+        self.device
+            .wait_for_fences(&[in_flight_fence], true, u64::MAX)?;
+
+        let (image_index, acquire_status) = self.device.acquire_next_image_khr(
+            self.data.swapchain,
+            u64::MAX,
+            self.data.image_available_semaphores[self.frame],
+            Fence::null(),
+        )?;
+        let image_index = image_index as usize;
+
+        // Another frame may still be rendering to this image.
+        let image_in_flight = self.data.images_in_flight[image_index];
+        if !image_in_flight.is_null() {
+            self.device
+                .wait_for_fences(&[image_in_flight], true, u64::MAX)?;
+        }
+
+        self.data.images_in_flight[image_index] = in_flight_fence;
+
+        let capture = self.begin_capture();
+        let submitted = self.submit_frame(image_index, capture.as_ref().map(|c| c.1));
+
+        // The image may only be read back while it is still acquired, so the
+        // capture is finished before the frame is presented.
+        if let Some((path, buffer, allocation)) = capture {
+            let copied = submitted.is_ok();
+            if let Err(e) = self.finish_capture(&path, buffer, allocation, copied) {
+                error!("Failed to capture frame to `{}`: {:#}", path.display(), e);
+            }
+        }
+
+        submitted?;
+
+        let signal_semaphores = &[self.data.render_finished_semaphores[image_index]];
+        let swapchains = &[self.data.swapchain];
+        let image_indices = &[image_index as u32];
+        let present_info = PresentInfoKHR::builder()
+            .wait_semaphores(signal_semaphores)
+            .swapchains(swapchains)
+            .image_indices(image_indices);
+
+        let present_status = self
+            .device
+            .queue_present_khr(self.data.present_queue, &present_info)?;
+
+        self.frame = (self.frame + 1) % self.data.max_frames_in_flight;
+
+        Ok(acquire_status == SuccessCode::SUBOPTIMAL_KHR
+            || present_status == SuccessCode::SUBOPTIMAL_KHR)
+    }
+
+    /// Creates the readback buffer for a pending windowed capture.
+    unsafe fn begin_capture(&mut self) -> Option<(PathBuf, Buffer, Allocation)> {
+        let path = self.data.pending_capture.take()?;
+        match self.create_readback_buffer() {
+            Result::Ok((buffer, allocation)) => Some((path, buffer, allocation)),
+            Err(e) => {
+                error!("Failed to capture frame to `{}`: {:#}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Waits for the current frame to finish copying into the readback
+    /// buffer, frees it and writes the capture to `path`.
+    unsafe fn finish_capture(
+        &self,
+        path: &Path,
+        buffer: Buffer,
+        allocation: Allocation,
+        copied: bool,
+    ) -> Result<()> {
+        let fence = self.data.in_flight_fences[self.frame];
+        let waited = if copied {
+            self.device
+                .wait_for_fences(&[fence], true, u64::MAX)
+                .map(|_| ())
+                .map_err(anyhow::Error::from)
+        } else {
+            Err(anyhow!("The frame was not submitted."))
+        };
+
+        // Free the buffer before propagating a failed readback.
+        let pixels = self.take_readback(buffer, allocation);
+        waited?;
+
+        self.save_capture(&pixels?, path)
+    }
+
+    /// Records and submits the frame for the swapchain image at
+    /// `image_index`, copying the image into `readback` if given.
+    unsafe fn submit_frame(&self, image_index: usize, readback: Option<Buffer>) -> Result<()> {
+        let in_flight_fence = self.data.in_flight_fences[self.frame];
+
+        self.data.commands.begin_frame(&self.device, self.frame)?;
+        let command_buffer = self.data.commands.primary(&self.device, self.frame, 0)?;
+        record_command_buffer(
+            &self.device,
+            &self.data,
+            command_buffer,
+            self.data.framebuffers[image_index],
+            readback.map(|buffer| {
+                let image = self.data.swapchain_images[image_index];
+                (image, ImageLayout::PRESENT_SRC_KHR, buffer)
+            }),
+        )?;
+
+        let wait_semaphores = &[self.data.image_available_semaphores[self.frame]];
+        let wait_stages = &[PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let command_buffers = &[command_buffer];
+        let signal_semaphores = &[self.data.render_finished_semaphores[image_index]];
+        let submit_info = SubmitInfo::builder()
+            .wait_semaphores(wait_semaphores)
+            .wait_dst_stage_mask(wait_stages)
+            .command_buffers(command_buffers)
+            .signal_semaphores(signal_semaphores);
+
+        self.device.reset_fences(&[in_flight_fence])?;
+        self.device
+            .queue_submit(self.data.graphics_queue, &[submit_info], in_flight_fence)?;
+
         Ok(())
     }
 
+    /// Rebuilds the pipeline if its shaders changed on disk. If the rebuild
+    /// fails, the previous pipeline stays in use.
+    unsafe fn reload_shaders(&mut self) {
+        let Some(watcher) = &self.data.shader_watcher else {
+            return;
+        };
+
+        let changed = watcher.changed();
+        if changed.is_empty() {
+            return;
+        }
+
+        for path in &changed {
+            info!("Shader `{}` changed.", path.display());
+        }
+
+        match self.rebuild_pipeline() {
+            Result::Ok(()) => info!("Reloaded the pipeline."),
+            Err(e) => error!(
+                "Failed to reload shaders, keeping the previous pipeline: {:#}",
+                e
+            ),
+        }
+    }
+
+    unsafe fn rebuild_pipeline(&mut self) -> Result<()> {
+        let Some(shaders) = &self.data.shaders else {
+            return Ok(());
+        };
+
+        let code = load_shader_code(shaders)?;
+        let pipeline =
+            pipeline_builder(&self.data, &code).build(&self.device, self.data.pipeline_cache)?;
+
+        // The previous pipeline may still be used by frames in flight.
+        self.device.device_wait_idle()?;
+        if let Some(previous) = self.data.pipeline.replace(pipeline) {
+            previous.destroy(&self.device);
+        }
+        self.data.shader_code = Some(code);
+
+        Ok(())
+    }
+
+    unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<()> {
+        self.device.device_wait_idle()?;
+        self.destroy_swapchain();
+        self.create_swapchain(window)
+    }
+
+    /// Replaces a lost surface with a new one for the same window. The
+    /// present queue family must still be able to present to it.
+    unsafe fn recreate_surface(&mut self, window: &Window) -> Result<()> {
+        self.device.device_wait_idle()?;
+        self.destroy_swapchain();
+        self.instance.destroy_surface_khr(self.data.surface, None);
+        self.data.surface = create_surface(&self.instance, window, window)?;
+
+        let indices =
+            QueueFamilyIndices::get(&self.instance, &self.data, self.data.physical_device)?;
+        if indices.present != Some(self.data.present_family) {
+            return Err(anyhow!(
+                "The recreated surface is not supported by the present queue."
+            ));
+        }
+
+        self.create_swapchain(window)
+    }
+
+    /// Creates the swapchain and everything that depends on it. The render
+    /// pass and pipeline are kept unless the color format changed, since the
+    /// viewport and scissor are dynamic.
+    unsafe fn create_swapchain(&mut self, window: &Window) -> Result<()> {
+        let format = self.data.color_format;
+        create_swapchain(window, &self.instance, &self.device, &mut self.data)?;
+        create_swapchain_image_views(&self.device, &mut self.data)?;
+
+        if self.data.color_format != format {
+            info!("Color format changed, rebuilding the render pass and pipeline.");
+            self.destroy_pipeline();
+            create_render_pass(&self.device, &mut self.data)?;
+            create_pipeline(&self.device, &mut self.data)?;
+        }
+
+        create_framebuffers(&self.device, &mut self.data)?;
+        Ok(())
+    }
+
+    /// Renders a frame into the offscreen color image and waits for it to
+    /// finish.
+    ///
+    /// # Safety
+    ///
+    /// The app must have been created with `create_headless` and not been
+    /// destroyed.
+    pub unsafe fn render_offscreen(&mut self) -> Result<(), AppError> {
+        self.check_validation_errors()?;
+        self.reload_shaders();
+        self.draw_offscreen().map_err(AppError::from)
+    }
+
+    unsafe fn draw_offscreen(&mut self) -> Result<()> {
+        let framebuffer = self.data.framebuffers[0];
+        let fence = self.data.in_flight_fences[0];
+
+        self.data.commands.begin_frame(&self.device, 0)?;
+        let command_buffer = self.data.commands.primary(&self.device, 0, 0)?;
+        record_command_buffer(&self.device, &self.data, command_buffer, framebuffer, None)?;
+
+        let command_buffers = &[command_buffer];
+        let submit_info = SubmitInfo::builder().command_buffers(command_buffers);
+
+        self.device.reset_fences(&[fence])?;
+        self.device
+            .queue_submit(self.data.graphics_queue, &[submit_info], fence)?;
+        self.device.wait_for_fences(&[fence], true, u64::MAX)?;
+        self.data.offscreen_rendered = true;
+
+        Ok(())
+    }
+
+    /// The layers and instance extensions that were actually enabled.
+    pub fn enabled_instance(&self) -> &NegotiatedInstance {
+        &self.data.enabled_instance
+    }
+
+    /// Removes and returns the validation messages reported since the last
+    /// call.
+    pub fn take_validation_messages(&self) -> Vec<ValidationMessage> {
+        self.data.validation_sink.take()
+    }
+
+    /// Fails with the first validation error reported since the previous
+    /// frame when the error policy asks for it.
+    fn check_validation_errors(&self) -> Result<(), AppError> {
+        if self.data.validation.error_policy != ValidationErrorPolicy::FailRender {
+            return Result::Ok(());
+        }
+
+        match self.data.validation_sink.take_error() {
+            Some(message) => Err(AppError::Validation(message)),
+            None => Result::Ok(()),
+        }
+    }
+
+    /// Copies the rendered color attachment into a host-visible buffer and
+    /// writes it to `path` as an RGBA8 PNG.
+    ///
+    /// Headless apps capture the last rendered frame right away. Windowed
+    /// apps capture the next frame before it is presented, and log failures
+    /// to write it.
+    ///
+    /// # Safety
+    ///
+    /// The app must not have been destroyed.
+    pub unsafe fn capture_frame(&mut self, path: impl AsRef<Path>) -> Result<(), AppError> {
+        if self.data.headless {
+            return self.write_capture(path.as_ref()).map_err(AppError::from);
+        }
+
+        if !self
+            .data
+            .swapchain_usage
+            .contains(ImageUsageFlags::TRANSFER_SRC)
+        {
+            return Err(
+                anyhow!("The surface does not support copying out of swapchain images.").into(),
+            );
+        }
+
+        self.data.pending_capture = Some(path.as_ref().into());
+        Result::Ok(())
+    }
+
+    unsafe fn write_capture(&self, path: &Path) -> Result<()> {
+        // The offscreen image is only in `TRANSFER_SRC_OPTIMAL` once a frame
+        // has been rendered into it.
+        if !self.data.offscreen_rendered {
+            return Err(anyhow!("No frame has been rendered to capture."));
+        }
+
+        // The image may still be in use by a frame in flight.
+        self.device.device_wait_idle()?;
+
+        let (buffer, allocation) = self.create_readback_buffer()?;
+
+        let result = self
+            .data
+            .commands
+            .immediate_submit(&self.device, |command_buffer| {
+                record_readback(
+                    &self.device,
+                    &self.data,
+                    command_buffer,
+                    self.data.offscreen_image,
+                    ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    buffer,
+                );
+                Ok(())
+            });
+
+        // Free the buffer before propagating a failed readback.
+        let pixels = self.take_readback(buffer, allocation);
+        result?;
+
+        self.save_capture(&pixels?, path)
+    }
+
+    /// The size of the color attachment's pixels in a readback buffer.
+    fn readback_size(&self) -> Result<DeviceSize> {
+        let extent = self.data.extent;
+        Ok((extent.width * extent.height) as u64
+            * capture::bytes_per_pixel(self.data.color_format)? as u64)
+    }
+
+    /// Creates a host-visible buffer the color attachment is copied into.
+    unsafe fn create_readback_buffer(&self) -> Result<(Buffer, Allocation)> {
+        self.data.allocator.create_buffer(
+            &self.device,
+            "capture buffer",
+            self.readback_size()?,
+            BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+            Strategy::Linear,
+        )
+    }
+
+    /// Returns the pixels copied into a readback buffer and frees it.
+    unsafe fn take_readback(&self, buffer: Buffer, allocation: Allocation) -> Result<Vec<u8>> {
+        let pixels = self.readback_size().and_then(|size| {
+            let mapped = allocation
+                .mapped_slice()
+                .ok_or_else(|| anyhow!("The capture buffer is not host visible."))?;
+            // The allocation may be padded past the end of the buffer.
+            Ok(mapped[..size as usize].to_vec())
+        });
+
+        self.device.destroy_buffer(buffer, None);
+        self.data.allocator.free(&self.device, allocation);
+
+        pixels
+    }
+
+    fn save_capture(&self, pixels: &[u8], path: &Path) -> Result<()> {
+        let extent = self.data.extent;
+        let rgba = capture::to_rgba8(self.data.color_format, pixels)?;
+        capture::write_png(path, extent.width, extent.height, &rgba)?;
+
+        info!("Captured frame to `{}`.", path.display());
+
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// The app must not be used again after this call.
     pub unsafe fn destroy(&mut self) {
-        if VALIDATION_ENABLED {
+        self.device.device_wait_idle().unwrap();
+
+        self.destroy_swapchain();
+        self.destroy_pipeline();
+        self.data
+            .in_flight_fences
+            .iter()
+            .for_each(|f| self.device.destroy_fence(*f, None));
+        self.data
+            .image_available_semaphores
+            .iter()
+            .for_each(|s| self.device.destroy_semaphore(*s, None));
+        self.data.commands.destroy(&self.device);
+
+        if let Some(path) = &self.data.pipeline_cache_path {
+            if let Err(e) = pipeline_cache::save(&self.device, self.data.pipeline_cache, path) {
+                warn!("Failed to save the pipeline cache: {:#}", e);
+            }
+        }
+        self.device
+            .destroy_pipeline_cache(self.data.pipeline_cache, None);
+
+        if self.data.headless {
+            self.device
+                .destroy_image_view(self.data.offscreen_image_view, None);
+            self.device.destroy_image(self.data.offscreen_image, None);
+            if let Some(allocation) = self.data.offscreen_image_allocation.take() {
+                self.data.allocator.free(&self.device, allocation);
+            }
+        }
+
+        self.data.allocator.destroy(&self.device);
+
+        self.device.destroy_device(None);
+
+        if !self.data.surface.is_null() {
+            self.instance.destroy_surface_khr(self.data.surface, None);
+        }
+
+        if !self.data.messenger.is_null() {
             self.instance
                 .destroy_debug_utils_messenger_ext(self.data.messenger, None);
         }
 
         self.instance.destroy_instance(None)
     }
+
+    /// Destroys everything that depends on the swapchain (or, in headless
+    /// mode, on the offscreen target's format and extent).
+    unsafe fn destroy_swapchain(&mut self) {
+        self.data
+            .framebuffers
+            .iter()
+            .for_each(|f| self.device.destroy_framebuffer(*f, None));
+        self.data.framebuffers.clear();
+        self.data
+            .swapchain_image_views
+            .iter()
+            .for_each(|v| self.device.destroy_image_view(*v, None));
+        self.data.swapchain_image_views.clear();
+        self.data
+            .render_finished_semaphores
+            .iter()
+            .for_each(|s| self.device.destroy_semaphore(*s, None));
+        self.data.render_finished_semaphores.clear();
+
+        if !self.data.swapchain.is_null() {
+            self.device.destroy_swapchain_khr(self.data.swapchain, None);
+            self.data.swapchain = SwapchainKHR::null();
+        }
+    }
+
+    /// Destroys the pipeline and the render pass it was built for.
+    unsafe fn destroy_pipeline(&mut self) {
+        if let Some(pipeline) = self.data.pipeline.take() {
+            pipeline.destroy(&self.device);
+        }
+        self.device.destroy_render_pass(self.data.render_pass, None);
+        self.data.render_pass = RenderPass::null();
+    }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Debug, Default)]
 pub struct AppData {
+    max_api_version: Version,
+    instance_version: Version,
+    device_version: Version,
+    validation: ValidationConfig,
+    validation_sink: Arc<ValidationSink>,
+    enabled_instance: NegotiatedInstance,
     messenger: DebugUtilsMessengerEXT,
+    surface: SurfaceKHR,
+    physical_device: PhysicalDevice,
+    present_family: u32,
+    graphics_queue: Queue,
+    present_queue: Queue,
+    // Dedicated compute and transfer queues, or the graphics queue when the
+    // device has none. Nothing submits to them yet.
+    compute_queue: Queue,
+    transfer_queue: Queue,
+    headless: bool,
+    clear_color: [f32; 4],
+    surface_formats: Vec<Format>,
+    present_modes: Vec<PresentModeKHR>,
+    color_format: Format,
+    extent: Extent2D,
+    swapchain: SwapchainKHR,
+    swapchain_images: Vec<Image>,
+    swapchain_image_views: Vec<ImageView>,
+    /// The usage the swapchain images were created with.
+    swapchain_usage: ImageUsageFlags,
+    /// Where to write the next frame, for windowed captures.
+    pending_capture: Option<PathBuf>,
+    offscreen_image: Image,
+    offscreen_image_allocation: Option<Allocation>,
+    offscreen_image_view: ImageView,
+    offscreen_rendered: bool,
+    render_pass: RenderPass,
+    allocator: Allocator,
+    shaders: Option<(ShaderSource, ShaderSource)>,
+    /// The SPIR-V the pipeline was last built from successfully, reused when
+    /// it has to be rebuilt for a new color format.
+    shader_code: Option<(Vec<u32>, Vec<u32>)>,
+    pipeline: Option<GraphicsPipeline>,
+    pipeline_cache: PipelineCache,
+    pipeline_cache_path: Option<PathBuf>,
+    shader_watcher: Option<Arc<ShaderWatcher>>,
+    framebuffers: Vec<Framebuffer>,
+    commands: CommandContext,
+    max_frames_in_flight: usize,
+    image_available_semaphores: Vec<Semaphore>,
+    /// Signalled when a swapchain image is ready to present, one per image.
+    render_finished_semaphores: Vec<Semaphore>,
+    in_flight_fences: Vec<Fence>,
+    /// The fence of the frame that last used each swapchain image.
+    images_in_flight: Vec<Fence>,
 }
 
+/// # Safety
+///
+/// `entry` must stay loaded for as long as the instance exists.
 pub unsafe fn create_instance(
-    window: &Window,
+    window: Option<&Window>,
     entry: &Entry,
     data: &mut AppData,
 ) -> Result<Instance> {
@@ -93,59 +737,1086 @@ pub unsafe fn create_instance(
         .application_version(make_version(1, 0, 0))
         .engine_name(b"No Engine\0")
         .engine_version(make_version(1, 0, 0))
-        .api_version(make_version(1, 0, 0));
+        .api_version(negotiate_api_version(entry, data)?.into());
 
-    let mut extensions = get_required_instance_extensions(window)
-        .iter()
-        .map(|extension| extension.as_ptr())
-        .collect::<Vec<_>>();
-
-    let available_layers = entry
-        .enumerate_instance_layer_properties()?
-        .iter()
-        .map(|layer| layer.layer_name)
-        .collect::<HashSet<_>>();
+    let validation_features = validation_features(entry, data)?;
 
-    if VALIDATION_ENABLED && !available_layers.contains(&VALIDATION_LAYER) {
-        return Err(anyhow!("Validation lauer requested but not suported"));
+    let mut requirements = instance_requirements(window, data)?;
+    if !validation_features.is_empty() {
+        requirements = requirements.optional_extension(VALIDATION_FEATURES_EXTENSION);
     }
 
-    if VALIDATION_ENABLED {
-        extensions.push(EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
-    }
-
-    let layers = if VALIDATION_ENABLED {
-        vec![VALIDATION_LAYER.as_ptr()]
-    } else {
-        Vec::new()
-    };
+    let enabled = requirements.negotiate(entry)?;
+    let layers = enabled.layer_names();
+    let extensions = enabled.extension_names();
 
-    let flags = if cfg!(target_os = "macos") && entry.version()? >= PORTABILITY_MACOS_VERSION {
+    let flags = if enabled.has_extension(&KHR_PORTABILITY_ENUMERATION_EXTENSION.name) {
         info!("Enabling extensions for macOS portability.");
-        extensions.push(KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION.name.as_ptr());
-        extensions.push(KHR_PORTABILITY_ENUMERATION_EXTENSION.name.as_ptr());
-
         InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
     } else {
         InstanceCreateFlags::empty()
     };
 
-    let info = InstanceCreateInfo::builder()
+    let messenger_enabled =
+        data.validation.enabled() && enabled.has_extension(&EXT_DEBUG_UTILS_EXTENSION.name);
+
+    let mut info = InstanceCreateInfo::builder()
         .application_info(&app_info)
         .enabled_layer_names(&layers)
         .enabled_extension_names(&extensions)
         .flags(flags);
 
-    let instance = entry.create_instance(&info, None)?;
+    // Chaining the messenger info reports problems inside `vkCreateInstance`
+    // and `vkDestroyInstance`, which the persistent messenger cannot see.
+    let mut debug_info = debug_messenger_info(data);
+    if messenger_enabled {
+        info = info.push_next(&mut debug_info);
+    }
 
-    if VALIDATION_ENABLED {
-        let debug_info = DebugUtilsMessengerCreateInfoEXT::builder()
-            .message_severity(DebugUtilsMessageSeverityFlagsEXT::all())
-            .message_type(DebugUtilsMessageTypeFlagsEXT::all())
-            .user_callback(Some(debug_callback));
+    let mut features_info =
+        ValidationFeaturesEXT::builder().enabled_validation_features(&validation_features);
+    if enabled.has_extension(&VALIDATION_FEATURES_EXTENSION) {
+        info = info.push_next(&mut features_info);
+    } else if !validation_features.is_empty() {
+        warn!("The validation layer does not support validation features.");
+    }
 
+    let instance = entry.create_instance(&info, None)?;
+
+    if messenger_enabled {
         data.messenger = instance.create_debug_utils_messenger_ext(&debug_info, None)?;
     }
 
+    data.enabled_instance = enabled;
+
     Ok(instance)
 }
+
+/// Everything `create_instance` needs from the loader: the WSI extensions for
+/// `window`, the configured layers and the validation and portability
+/// extensions.
+fn instance_requirements(window: Option<&Window>, data: &AppData) -> Result<InstanceRequirements> {
+    let mut requirements = InstanceRequirements::new();
+
+    for extension in window
+        .map(|window| get_required_instance_extensions(window))
+        .unwrap_or_default()
+    {
+        requirements = requirements.require_extension(**extension);
+    }
+
+    if data.validation.enabled() {
+        requirements = requirements
+            .require_layer(VALIDATION_LAYER)
+            .optional_extension(EXT_DEBUG_UTILS_EXTENSION.name);
+    }
+
+    for name in &data.validation.extra_layers {
+        let layer = ExtensionName::from_cstr(&CString::new(name.as_str())?);
+        requirements = requirements.require_layer(layer);
+    }
+
+    if cfg!(target_os = "macos") {
+        requirements = requirements
+            .optional_extension(KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION.name)
+            .optional_extension(KHR_PORTABILITY_ENUMERATION_EXTENSION.name);
+    }
+
+    Ok(requirements)
+}
+
+/// The requested validation features the loaded validation layer supports.
+/// Unsupported features are skipped with a warning.
+unsafe fn validation_features(
+    entry: &Entry,
+    data: &AppData,
+) -> Result<Vec<ValidationFeatureEnableEXT>> {
+    let features = data.validation.features;
+    if !data.validation.enabled() || !features.any() {
+        return Ok(vec![]);
+    }
+
+    let layer_version = entry
+        .enumerate_instance_layer_properties()?
+        .iter()
+        .find(|l| l.layer_name == VALIDATION_LAYER)
+        .map(|l| Version::from(l.spec_version))
+        .unwrap_or_default();
+
+    // GPU-assisted validation and debug printf share the same instrumentation
+    // and cannot be enabled together.
+    let debug_printf = features.debug_printf && !features.gpu_assisted;
+    if features.debug_printf && !debug_printf {
+        warn!("Debug printf cannot be combined with GPU-assisted validation.");
+    }
+
+    let requested = [
+        (
+            features.gpu_assisted,
+            ValidationFeatureEnableEXT::GPU_ASSISTED,
+            Version::new(1, 1, 106),
+        ),
+        (
+            features.best_practices,
+            ValidationFeatureEnableEXT::BEST_PRACTICES,
+            Version::new(1, 2, 131),
+        ),
+        (
+            debug_printf,
+            ValidationFeatureEnableEXT::DEBUG_PRINTF,
+            Version::new(1, 2, 135),
+        ),
+        (
+            features.synchronization,
+            ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION,
+            Version::new(1, 2, 141),
+        ),
+    ];
+
+    let mut enabled = vec![];
+    for (requested, feature, min_version) in requested {
+        if !requested {
+            continue;
+        }
+
+        if layer_version < min_version {
+            warn!(
+                "Validation feature {:?} needs validation layer {} (found {}).",
+                feature, min_version, layer_version
+            );
+        } else {
+            info!("Enabling validation feature {:?}.", feature);
+            enabled.push(feature);
+        }
+    }
+
+    Ok(enabled)
+}
+
+fn debug_messenger_info(data: &AppData) -> DebugUtilsMessengerCreateInfoEXTBuilder<'static> {
+    let mut info = DebugUtilsMessengerCreateInfoEXT::builder()
+        .message_severity(data.validation.severity)
+        .message_type(data.validation.types)
+        .user_callback(Some(debug_callback));
+    info.user_data = Arc::as_ptr(&data.validation_sink) as *mut c_void;
+
+    info
+}
+
+/// Picks the highest instance version the loader supports, capped at the
+/// configured ceiling. A 1.0 loader rejects any higher version, so the
+/// request can never exceed what `vkEnumerateInstanceVersion` reports.
+unsafe fn negotiate_api_version(entry: &Entry, data: &mut AppData) -> Result<Version> {
+    let loader_version = major_minor(entry.version()?);
+    let version = loader_version.min(major_minor(data.max_api_version));
+
+    info!(
+        "Requesting Vulkan {} (loader supports {}, ceiling {}).",
+        version, loader_version, data.max_api_version
+    );
+
+    data.instance_version = version;
+
+    Ok(version)
+}
+
+fn major_minor(version: Version) -> Version {
+    Version::new(version.major, version.minor, 0)
+}
+
+#[derive(Debug, Error)]
+pub enum SuitabilityError {
+    #[error("API version {found} is below the required {required}")]
+    ApiVersion { found: Version, required: Version },
+    #[error("missing {0} queue family")]
+    MissingQueueFamily(&'static str),
+    #[error("missing device extensions: {}", .0.join(", "))]
+    MissingExtensions(Vec<String>),
+    #[error("insufficient swapchain support")]
+    SwapchainSupport,
+}
+
+unsafe fn pick_physical_device(
+    instance: &Instance,
+    config: &AppConfig,
+    data: &mut AppData,
+) -> Result<()> {
+    let mut best: Option<(u32, PhysicalDevice, String)> = None;
+    let mut rejections = vec![];
+
+    for (index, physical_device) in instance
+        .enumerate_physical_devices()?
+        .into_iter()
+        .enumerate()
+    {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let name = properties.device_name.to_string();
+
+        if let Some(selector) = &config.device {
+            if !selector.matches(index, &name) {
+                continue;
+            }
+
+            let score = rate_physical_device(instance, data, physical_device).map_err(|e| {
+                AppError::NoSuitableDevice(vec![format!(
+                    "forced physical device {} (`{}`) is unsuitable: {}",
+                    index, name, e
+                )])
+            })?;
+
+            info!(
+                "Using forced physical device {} (`{}`, score {}).",
+                index, name, score
+            );
+            select_physical_device(instance, data, physical_device);
+            return Ok(());
+        }
+
+        match rate_physical_device(instance, data, physical_device) {
+            Result::Ok(score) => {
+                info!("Physical device {} (`{}`) scored {}.", index, name, score);
+                if best
+                    .as_ref()
+                    .is_none_or(|(best_score, ..)| score > *best_score)
+                {
+                    best = Some((score, physical_device, name));
+                }
+            }
+            Err(error) => {
+                warn!("Skipping physical device {} (`{}`): {}", index, name, error);
+                rejections.push(format!("`{}`: {}", name, error));
+            }
+        }
+    }
+
+    if let Some(selector) = &config.device {
+        return Err(AppError::NoSuitableDevice(vec![format!(
+            "no physical device matches {:?}",
+            selector
+        )])
+        .into());
+    }
+
+    if rejections.is_empty() {
+        rejections.push("no physical devices found".into());
+    }
+
+    let (score, physical_device, name) = best.ok_or(AppError::NoSuitableDevice(rejections))?;
+
+    info!("Selected physical device (`{}`, score {}).", name, score);
+    select_physical_device(instance, data, physical_device);
+
+    Ok(())
+}
+
+unsafe fn select_physical_device(
+    instance: &Instance,
+    data: &mut AppData,
+    physical_device: PhysicalDevice,
+) {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let device_version = major_minor(Version::from(properties.api_version));
+
+    data.physical_device = physical_device;
+    data.device_version = device_version.min(data.instance_version);
+
+    info!("Using Vulkan {} on the device.", data.device_version);
+}
+
+unsafe fn rate_physical_device(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: PhysicalDevice,
+) -> Result<u32, SuitabilityError> {
+    let properties = instance.get_physical_device_properties(physical_device);
+
+    let api_version = Version::from(properties.api_version);
+    if api_version < MIN_DEVICE_API_VERSION {
+        return Err(SuitabilityError::ApiVersion {
+            found: api_version,
+            required: MIN_DEVICE_API_VERSION,
+        });
+    }
+
+    QueueFamilyIndices::get(instance, data, physical_device)?;
+    check_physical_device_extensions(instance, data, physical_device)?;
+
+    if !data.surface.is_null() {
+        let support = SwapchainSupport::get(instance, data, physical_device)
+            .map_err(|_| SuitabilityError::SwapchainSupport)?;
+        if support.formats.is_empty() || support.present_modes.is_empty() {
+            return Err(SuitabilityError::SwapchainSupport);
+        }
+    }
+
+    let type_score = match properties.device_type {
+        PhysicalDeviceType::DISCRETE_GPU => 1000,
+        PhysicalDeviceType::INTEGRATED_GPU => 500,
+        PhysicalDeviceType::VIRTUAL_GPU => 250,
+        PhysicalDeviceType::CPU => 100,
+        _ => 10,
+    };
+
+    Result::Ok(type_score + api_version.minor * 10)
+}
+
+/// Headless rendering never presents, so it does not need the swapchain
+/// extension and can run on devices without WSI support.
+fn required_device_extensions(data: &AppData) -> &'static [ExtensionName] {
+    if data.headless {
+        &[]
+    } else {
+        DEVICE_EXTENSIONS
+    }
+}
+
+unsafe fn check_physical_device_extensions(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: PhysicalDevice,
+) -> Result<(), SuitabilityError> {
+    let extensions = instance
+        .enumerate_device_extension_properties(physical_device, None)
+        .map(|e| e.iter().map(|e| e.extension_name).collect::<HashSet<_>>())
+        .unwrap_or_default();
+
+    let missing = required_device_extensions(data)
+        .iter()
+        .filter(|e| !extensions.contains(e))
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        Result::Ok(())
+    } else {
+        Err(SuitabilityError::MissingExtensions(missing))
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct QueueFamilyIndices {
+    pub graphics: u32,
+    pub present: Option<u32>,
+    /// A family supporting compute but not graphics, for async compute.
+    pub compute: Option<u32>,
+    /// A family supporting only transfers, usually backed by a DMA engine.
+    pub transfer: Option<u32>,
+}
+
+impl QueueFamilyIndices {
+    /// Finds the queue families of `physical_device`. A present family is
+    /// only looked for (and then required) when `data` has a surface.
+    ///
+    /// # Safety
+    ///
+    /// `physical_device` and the surface in `data`, if any, must belong to
+    /// `instance`.
+    pub unsafe fn get(
+        instance: &Instance,
+        data: &AppData,
+        physical_device: PhysicalDevice,
+    ) -> Result<Self, SuitabilityError> {
+        let properties = instance.get_physical_device_queue_family_properties(physical_device);
+
+        let find = |required: QueueFlags, excluded: QueueFlags| {
+            properties
+                .iter()
+                .position(|p| {
+                    p.queue_count > 0
+                        && p.queue_flags.contains(required)
+                        && !p.queue_flags.intersects(excluded)
+                })
+                .map(|i| i as u32)
+        };
+
+        let graphics = find(QueueFlags::GRAPHICS, QueueFlags::empty())
+            .ok_or(SuitabilityError::MissingQueueFamily("graphics"))?;
+        let compute = find(QueueFlags::COMPUTE, QueueFlags::GRAPHICS);
+        let transfer = find(
+            QueueFlags::TRANSFER,
+            QueueFlags::GRAPHICS | QueueFlags::COMPUTE,
+        );
+
+        let present = if data.surface.is_null() {
+            None
+        } else {
+            // Prefer presenting from the graphics family to avoid ownership
+            // transfers between queues.
+            let supports_present = |index: u32| {
+                instance
+                    .get_physical_device_surface_support_khr(physical_device, index, data.surface)
+                    .unwrap_or(false)
+            };
+
+            let present = if supports_present(graphics) {
+                graphics
+            } else {
+                (0..properties.len() as u32)
+                    .find(|i| supports_present(*i))
+                    .ok_or(SuitabilityError::MissingQueueFamily("present"))?
+            };
+
+            Some(present)
+        };
+
+        Result::Ok(Self {
+            graphics,
+            present,
+            compute,
+            transfer,
+        })
+    }
+
+    /// The distinct family indices that need a queue on the logical device.
+    pub fn unique(&self) -> Vec<u32> {
+        let mut indices = vec![self.graphics];
+        for index in [self.present, self.compute, self.transfer]
+            .into_iter()
+            .flatten()
+        {
+            if !indices.contains(&index) {
+                indices.push(index);
+            }
+        }
+
+        indices
+    }
+}
+
+unsafe fn create_logical_device(instance: &Instance, data: &mut AppData) -> Result<Device> {
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+    info!("Using queue families {:?}.", indices);
+
+    let queue_priorities = &[1.0];
+    let queue_infos = indices
+        .unique()
+        .iter()
+        .map(|i| {
+            DeviceQueueCreateInfo::builder()
+                .queue_family_index(*i)
+                .queue_priorities(queue_priorities)
+        })
+        .collect::<Vec<_>>();
+
+    // Device layers are deprecated, but older implementations still expect
+    // them to match the instance layers.
+    let layers = data.enabled_instance.layer_names();
+
+    let mut extensions = required_device_extensions(data)
+        .iter()
+        .map(|n| n.as_ptr())
+        .collect::<Vec<_>>();
+
+    if data
+        .enabled_instance
+        .has_extension(&KHR_PORTABILITY_ENUMERATION_EXTENSION.name)
+    {
+        extensions.push(KHR_PORTABILITY_SUBSET_EXTENSION.name.as_ptr());
+    }
+
+    let features = PhysicalDeviceFeatures::builder();
+
+    let info = DeviceCreateInfo::builder()
+        .queue_create_infos(&queue_infos)
+        .enabled_layer_names(&layers)
+        .enabled_extension_names(&extensions)
+        .enabled_features(&features);
+
+    let device = instance.create_device(data.physical_device, &info, None)?;
+
+    data.graphics_queue = device.get_device_queue(indices.graphics, 0);
+    data.present_family = indices.present.unwrap_or(indices.graphics);
+    data.present_queue = device.get_device_queue(data.present_family, 0);
+    data.compute_queue = indices
+        .compute
+        .map_or(data.graphics_queue, |i| device.get_device_queue(i, 0));
+    data.transfer_queue = indices
+        .transfer
+        .map_or(data.graphics_queue, |i| device.get_device_queue(i, 0));
+
+    Ok(device)
+}
+
+#[derive(Clone, Debug)]
+pub struct SwapchainSupport {
+    pub capabilities: SurfaceCapabilitiesKHR,
+    pub formats: Vec<SurfaceFormatKHR>,
+    pub present_modes: Vec<PresentModeKHR>,
+}
+
+impl SwapchainSupport {
+    /// # Safety
+    ///
+    /// `physical_device` and the surface in `data` must belong to `instance`.
+    pub unsafe fn get(
+        instance: &Instance,
+        data: &AppData,
+        physical_device: PhysicalDevice,
+    ) -> Result<Self> {
+        Ok(Self {
+            capabilities: instance
+                .get_physical_device_surface_capabilities_khr(physical_device, data.surface)?,
+            formats: instance
+                .get_physical_device_surface_formats_khr(physical_device, data.surface)?,
+            present_modes: instance
+                .get_physical_device_surface_present_modes_khr(physical_device, data.surface)?,
+        })
+    }
+}
+
+unsafe fn create_swapchain(
+    window: &Window,
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+    let support = SwapchainSupport::get(instance, data, data.physical_device)?;
+
+    let surface_format = get_swapchain_surface_format(data, &support.formats);
+    let present_mode = get_swapchain_present_mode(data, &support.present_modes);
+    let extent = get_swapchain_extent(window, support.capabilities);
+
+    info!(
+        "Using swapchain format {:?} ({:?}), present mode {:?} and extent {}x{}.",
+        surface_format.format,
+        surface_format.color_space,
+        present_mode,
+        extent.width,
+        extent.height
+    );
+
+    let mut image_count = support.capabilities.min_image_count + 1;
+    if support.capabilities.max_image_count != 0
+        && image_count > support.capabilities.max_image_count
+    {
+        image_count = support.capabilities.max_image_count;
+    }
+
+    let mut queue_family_indices = vec![];
+    let image_sharing_mode = if indices.present != Some(indices.graphics) {
+        queue_family_indices.push(indices.graphics);
+        queue_family_indices.extend(indices.present);
+        SharingMode::CONCURRENT
+    } else {
+        SharingMode::EXCLUSIVE
+    };
+
+    // Captures copy out of swapchain images when the surface allows it.
+    let mut image_usage = ImageUsageFlags::COLOR_ATTACHMENT;
+    if support
+        .capabilities
+        .supported_usage_flags
+        .contains(ImageUsageFlags::TRANSFER_SRC)
+    {
+        image_usage |= ImageUsageFlags::TRANSFER_SRC;
+    }
+
+    let info = SwapchainCreateInfoKHR::builder()
+        .surface(data.surface)
+        .min_image_count(image_count)
+        .image_format(surface_format.format)
+        .image_color_space(surface_format.color_space)
+        .image_extent(extent)
+        .image_array_layers(1)
+        .image_usage(image_usage)
+        .image_sharing_mode(image_sharing_mode)
+        .queue_family_indices(&queue_family_indices)
+        .pre_transform(support.capabilities.current_transform)
+        .composite_alpha(CompositeAlphaFlagsKHR::OPAQUE)
+        .present_mode(present_mode)
+        .clipped(true)
+        .old_swapchain(SwapchainKHR::null());
+
+    data.swapchain = device.create_swapchain_khr(&info, None)?;
+    data.swapchain_images = device.get_swapchain_images_khr(data.swapchain)?;
+    data.color_format = surface_format.format;
+    data.extent = extent;
+    data.swapchain_usage = image_usage;
+    data.images_in_flight = vec![Fence::null(); data.swapchain_images.len()];
+
+    // The presentation engine may still wait on an image's semaphore when
+    // the next frame in flight starts, so there is one per image rather than
+    // one per frame.
+    let semaphore_info = SemaphoreCreateInfo::builder();
+    for _ in 0..data.swapchain_images.len() {
+        data.render_finished_semaphores
+            .push(device.create_semaphore(&semaphore_info, None)?);
+    }
+
+    Ok(())
+}
+
+/// The first configured format the surface supports with an sRGB color
+/// space, falling back to the first format the surface reports.
+fn get_swapchain_surface_format(data: &AppData, formats: &[SurfaceFormatKHR]) -> SurfaceFormatKHR {
+    data.surface_formats
+        .iter()
+        .find_map(|preferred| {
+            formats
+                .iter()
+                .find(|f| f.format == *preferred && f.color_space == ColorSpaceKHR::SRGB_NONLINEAR)
+        })
+        .cloned()
+        .unwrap_or_else(|| {
+            warn!(
+                "No preferred surface format is supported, using {:?}.",
+                formats[0].format
+            );
+            formats[0]
+        })
+}
+
+/// The first configured present mode the surface supports. FIFO is always
+/// supported, so it is the fallback.
+fn get_swapchain_present_mode(data: &AppData, present_modes: &[PresentModeKHR]) -> PresentModeKHR {
+    data.present_modes
+        .iter()
+        .find(|m| present_modes.contains(m))
+        .cloned()
+        .unwrap_or(PresentModeKHR::FIFO)
+}
+
+fn get_swapchain_extent(window: &Window, capabilities: SurfaceCapabilitiesKHR) -> Extent2D {
+    if capabilities.current_extent.width != u32::MAX {
+        capabilities.current_extent
+    } else {
+        let size = window.inner_size();
+        let clamp = |min: u32, max: u32, v: u32| min.max(max.min(v));
+        Extent2D::builder()
+            .width(clamp(
+                capabilities.min_image_extent.width,
+                capabilities.max_image_extent.width,
+                size.width,
+            ))
+            .height(clamp(
+                capabilities.min_image_extent.height,
+                capabilities.max_image_extent.height,
+                size.height,
+            ))
+            .build()
+    }
+}
+
+unsafe fn create_swapchain_image_views(device: &Device, data: &mut AppData) -> Result<()> {
+    data.swapchain_image_views = data
+        .swapchain_images
+        .iter()
+        .map(|i| create_image_view(device, *i, data.color_format))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(())
+}
+
+unsafe fn create_offscreen_target(device: &Device, data: &mut AppData) -> Result<()> {
+    let info = ImageCreateInfo::builder()
+        .image_type(ImageType::_2D)
+        .extent(Extent3D {
+            width: data.extent.width,
+            height: data.extent.height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .format(data.color_format)
+        .tiling(ImageTiling::OPTIMAL)
+        .initial_layout(ImageLayout::UNDEFINED)
+        .usage(ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::TRANSFER_SRC)
+        .sharing_mode(SharingMode::EXCLUSIVE)
+        .samples(SampleCountFlags::_1);
+
+    data.offscreen_image = device.create_image(&info, None)?;
+
+    let allocation = data.allocator.allocate_image(
+        device,
+        "offscreen image",
+        data.offscreen_image,
+        MemoryLocation::GpuOnly,
+        Strategy::FreeList,
+    )?;
+    data.offscreen_image_allocation = Some(allocation);
+
+    data.offscreen_image_view = create_image_view(device, data.offscreen_image, data.color_format)?;
+
+    Ok(())
+}
+
+unsafe fn create_image_view(device: &Device, image: Image, format: Format) -> Result<ImageView> {
+    let subresource_range = ImageSubresourceRange::builder()
+        .aspect_mask(ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let info = ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(ImageViewType::_2D)
+        .format(format)
+        .subresource_range(subresource_range);
+
+    Ok(device.create_image_view(&info, None)?)
+}
+
+unsafe fn transition_image_layout(
+    device: &Device,
+    command_buffer: CommandBuffer,
+    image: Image,
+    old_layout: ImageLayout,
+    new_layout: ImageLayout,
+) {
+    let access_mask = |layout| match layout {
+        ImageLayout::TRANSFER_SRC_OPTIMAL => AccessFlags::TRANSFER_READ,
+        ImageLayout::TRANSFER_DST_OPTIMAL => AccessFlags::TRANSFER_WRITE,
+        ImageLayout::COLOR_ATTACHMENT_OPTIMAL => AccessFlags::COLOR_ATTACHMENT_WRITE,
+        // Swapchain images are only read back right after the render pass
+        // that wrote them.
+        ImageLayout::PRESENT_SRC_KHR => AccessFlags::COLOR_ATTACHMENT_WRITE,
+        _ => AccessFlags::empty(),
+    };
+
+    let subresource = ImageSubresourceRange::builder()
+        .aspect_mask(ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let barrier = ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource)
+        .src_access_mask(access_mask(old_layout))
+        .dst_access_mask(access_mask(new_layout));
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        PipelineStageFlags::ALL_COMMANDS,
+        PipelineStageFlags::ALL_COMMANDS,
+        DependencyFlags::empty(),
+        &[] as &[MemoryBarrier],
+        &[] as &[BufferMemoryBarrier],
+        &[barrier],
+    );
+}
+
+unsafe fn create_render_pass(device: &Device, data: &mut AppData) -> Result<()> {
+    // Offscreen images are read back after rendering, swapchain images are
+    // handed to the presentation engine.
+    let final_layout = if data.headless {
+        ImageLayout::TRANSFER_SRC_OPTIMAL
+    } else {
+        ImageLayout::PRESENT_SRC_KHR
+    };
+
+    let color_attachment = AttachmentDescription::builder()
+        .format(data.color_format)
+        .samples(SampleCountFlags::_1)
+        .load_op(AttachmentLoadOp::CLEAR)
+        .store_op(AttachmentStoreOp::STORE)
+        .stencil_load_op(AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+        .initial_layout(ImageLayout::UNDEFINED)
+        .final_layout(final_layout);
+
+    let color_attachment_ref = AttachmentReference::builder()
+        .attachment(0)
+        .layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let color_attachments = &[color_attachment_ref];
+    let subpass = SubpassDescription::builder()
+        .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
+        .color_attachments(color_attachments);
+
+    let dependency = SubpassDependency::builder()
+        .src_subpass(SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(AccessFlags::empty())
+        .dst_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .dst_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE);
+
+    // Captures copy the offscreen image in a later submission, which has to
+    // see the color attachment writes.
+    let readback_dependency = SubpassDependency::builder()
+        .src_subpass(0)
+        .dst_subpass(SUBPASS_EXTERNAL)
+        .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(PipelineStageFlags::TRANSFER)
+        .dst_access_mask(AccessFlags::TRANSFER_READ);
+
+    let attachments = &[color_attachment];
+    let subpasses = &[subpass];
+    let dependencies = if data.headless {
+        &[dependency, readback_dependency][..]
+    } else {
+        &[dependency][..]
+    };
+    let info = RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+
+    data.render_pass = device.create_render_pass(&info, None)?;
+
+    Ok(())
+}
+
+/// Loads the vertex and fragment shaders, compiling WGSL and GLSL sources.
+fn load_shader_code(
+    (vertex_shader, fragment_shader): &(ShaderSource, ShaderSource),
+) -> Result<(Vec<u32>, Vec<u32>)> {
+    Ok((
+        vertex_shader.load(ShaderStage::Vertex)?,
+        fragment_shader.load(ShaderStage::Fragment)?,
+    ))
+}
+
+fn pipeline_builder(data: &AppData, (vertex, fragment): &(Vec<u32>, Vec<u32>)) -> PipelineBuilder {
+    PipelineBuilder::new()
+        .vertex_shader(ShaderSource::Spirv(vertex.clone()))
+        .fragment_shader(ShaderSource::Spirv(fragment.clone()))
+        .reflect()
+        .render_pass(data.render_pass, 0)
+}
+
+unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    if let Some(code) = &data.shader_code {
+        let pipeline = pipeline_builder(data, code).build(device, data.pipeline_cache)?;
+        data.pipeline = Some(pipeline);
+    }
+
+    Ok(())
+}
+
+/// Watching is a development aid, so failing to set it up is not an error.
+fn create_shader_watcher(data: &mut AppData) {
+    let Some((vertex_shader, fragment_shader)) = &data.shaders else {
+        return;
+    };
+
+    let paths = [vertex_shader, fragment_shader]
+        .into_iter()
+        .filter_map(|s| s.path());
+
+    match ShaderWatcher::new(paths) {
+        Result::Ok(watcher) => data.shader_watcher = Some(Arc::new(watcher)),
+        Err(e) => warn!("Shader hot-reload is disabled: {:#}", e),
+    }
+}
+
+unsafe fn create_pipeline_cache(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let properties = instance.get_physical_device_properties(data.physical_device);
+    data.pipeline_cache =
+        pipeline_cache::load(device, &properties, data.pipeline_cache_path.as_deref())?;
+
+    Ok(())
+}
+
+unsafe fn create_framebuffers(device: &Device, data: &mut AppData) -> Result<()> {
+    let views = if data.headless {
+        vec![data.offscreen_image_view]
+    } else {
+        data.swapchain_image_views.clone()
+    };
+
+    data.framebuffers = views
+        .iter()
+        .map(|v| {
+            let attachments = &[*v];
+            let info = FramebufferCreateInfo::builder()
+                .render_pass(data.render_pass)
+                .attachments(attachments)
+                .width(data.extent.width)
+                .height(data.extent.height)
+                .layers(1);
+
+            device.create_framebuffer(&info, None)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(())
+}
+
+unsafe fn create_allocator(instance: &Instance, data: &mut AppData) {
+    data.allocator = Allocator::new(instance, data.physical_device, DEFAULT_BLOCK_SIZE);
+}
+
+unsafe fn create_command_context(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+    data.commands = CommandContext::new(
+        device,
+        indices.graphics,
+        data.graphics_queue,
+        data.max_frames_in_flight,
+        1,
+    )?;
+
+    Ok(())
+}
+
+unsafe fn create_sync_objects(device: &Device, data: &mut AppData) -> Result<()> {
+    let semaphore_info = SemaphoreCreateInfo::builder();
+    let fence_info = FenceCreateInfo::builder().flags(FenceCreateFlags::SIGNALED);
+
+    for _ in 0..data.max_frames_in_flight {
+        data.image_available_semaphores
+            .push(device.create_semaphore(&semaphore_info, None)?);
+        data.in_flight_fences
+            .push(device.create_fence(&fence_info, None)?);
+    }
+
+    Ok(())
+}
+
+/// Records a frame into `framebuffer`. Both the windowed and the headless
+/// paths render through here. With `readback`, the given image, which is in
+/// the given layout after the render pass, is also copied into the buffer.
+unsafe fn record_command_buffer(
+    device: &Device,
+    data: &AppData,
+    command_buffer: CommandBuffer,
+    framebuffer: Framebuffer,
+    readback: Option<(Image, ImageLayout, Buffer)>,
+) -> Result<()> {
+    let info = CommandBufferBeginInfo::builder().flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    let render_area = Rect2D::builder()
+        .offset(Offset2D::default())
+        .extent(data.extent);
+
+    let color_clear_value = ClearValue {
+        color: ClearColorValue {
+            float32: data.clear_color,
+        },
+    };
+
+    let clear_values = &[color_clear_value];
+    let info = RenderPassBeginInfo::builder()
+        .render_pass(data.render_pass)
+        .framebuffer(framebuffer)
+        .render_area(render_area)
+        .clear_values(clear_values);
+
+    device.cmd_begin_render_pass(command_buffer, &info, SubpassContents::INLINE);
+
+    if let Some(pipeline) = &data.pipeline {
+        let viewport = Viewport::builder()
+            .x(0.0)
+            .y(0.0)
+            .width(data.extent.width as f32)
+            .height(data.extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0);
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            PipelineBindPoint::GRAPHICS,
+            pipeline.pipeline,
+        );
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+    }
+
+    device.cmd_end_render_pass(command_buffer);
+
+    if let Some((image, layout, buffer)) = readback {
+        record_readback(device, data, command_buffer, image, layout, buffer);
+    }
+
+    device.end_command_buffer(command_buffer)?;
+
+    Ok(())
+}
+
+/// Copies the color attachment `image` in `layout` into `buffer`, leaving
+/// the image in `layout`.
+unsafe fn record_readback(
+    device: &Device,
+    data: &AppData,
+    command_buffer: CommandBuffer,
+    image: Image,
+    layout: ImageLayout,
+    buffer: Buffer,
+) {
+    if layout != ImageLayout::TRANSFER_SRC_OPTIMAL {
+        transition_image_layout(
+            device,
+            command_buffer,
+            image,
+            layout,
+            ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+    }
+
+    let subresource = ImageSubresourceLayers::builder()
+        .aspect_mask(ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let region = BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(subresource)
+        .image_offset(Offset3D::default())
+        .image_extent(Extent3D {
+            width: data.extent.width,
+            height: data.extent.height,
+            depth: 1,
+        });
+
+    device.cmd_copy_image_to_buffer(
+        command_buffer,
+        image,
+        ImageLayout::TRANSFER_SRC_OPTIMAL,
+        buffer,
+        &[region],
+    );
+
+    // The host reads the buffer once the submission's fence is signaled.
+    let buffer_barrier = BufferMemoryBarrier::builder()
+        .src_access_mask(AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(AccessFlags::HOST_READ)
+        .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+        .buffer(buffer)
+        .offset(0)
+        .size(WHOLE_SIZE as u64);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        PipelineStageFlags::TRANSFER,
+        PipelineStageFlags::HOST,
+        DependencyFlags::empty(),
+        &[] as &[MemoryBarrier],
+        &[buffer_barrier],
+        &[] as &[ImageMemoryBarrier],
+    );
+
+    if layout != ImageLayout::TRANSFER_SRC_OPTIMAL {
+        transition_image_layout(
+            device,
+            command_buffer,
+            image,
+            ImageLayout::TRANSFER_SRC_OPTIMAL,
+            layout,
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use anyhow::{anyhow, Result};
+use std::{fs::File, io::BufWriter, path::Path};
+use vulkanalia::vk::Format;
+
+/// The size of a texel of a capturable color format.
+pub fn bytes_per_pixel(format: Format) -> Result<u32> {
+    match format {
+        Format::R8G8B8A8_UNORM
+        | Format::R8G8B8A8_SRGB
+        | Format::B8G8R8A8_UNORM
+        | Format::B8G8R8A8_SRGB => Ok(4),
+        _ => Err(anyhow!("Capturing {:?} images is not supported.", format)),
+    }
+}
+
+/// Converts tightly packed texels of `format` to RGBA8.
+pub fn to_rgba8(format: Format, pixels: &[u8]) -> Result<Vec<u8>> {
+    match format {
+        Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => Ok(pixels.to_vec()),
+        Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => Ok(pixels
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]])
+            .collect()),
+        _ => Err(anyhow!("Capturing {:?} images is not supported.", format)),
+    }
+}
+
+pub fn write_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+    let file = File::create(path)?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+
+    Ok(())
+}
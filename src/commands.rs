@@ -0,0 +1,222 @@
+use anyhow::{anyhow, Result};
+use std::sync::{Arc, Mutex};
+use vulkanalia::{
+    prelude::v1_0::*,
+    vk::{
+        CommandBuffer, CommandBufferAllocateInfo, CommandBufferBeginInfo, CommandBufferLevel,
+        CommandBufferUsageFlags, CommandPool, CommandPoolCreateFlags, CommandPoolCreateInfo,
+        CommandPoolResetFlags, Fence, FenceCreateInfo, Queue, SubmitInfo,
+    },
+};
+
+/// Command buffers handed out from one pool. Resetting the pool returns
+/// them to the initial state, so they are reused instead of freed.
+#[derive(Debug, Default)]
+struct PoolState {
+    pool: CommandPool,
+    primary: Vec<CommandBuffer>,
+    secondary: Vec<CommandBuffer>,
+    next_primary: usize,
+    next_secondary: usize,
+}
+
+impl PoolState {
+    unsafe fn new(
+        device: &Device,
+        queue_family: u32,
+        flags: CommandPoolCreateFlags,
+    ) -> Result<Self> {
+        let info = CommandPoolCreateInfo::builder()
+            .flags(flags)
+            .queue_family_index(queue_family);
+
+        Ok(Self {
+            pool: device.create_command_pool(&info, None)?,
+            ..Default::default()
+        })
+    }
+
+    unsafe fn allocate(
+        &mut self,
+        device: &Device,
+        level: CommandBufferLevel,
+    ) -> Result<CommandBuffer> {
+        let (buffers, next) = if level == CommandBufferLevel::PRIMARY {
+            (&mut self.primary, &mut self.next_primary)
+        } else {
+            (&mut self.secondary, &mut self.next_secondary)
+        };
+
+        if *next == buffers.len() {
+            let info = CommandBufferAllocateInfo::builder()
+                .command_pool(self.pool)
+                .level(level)
+                .command_buffer_count(1);
+            buffers.push(device.allocate_command_buffers(&info)?[0]);
+        }
+
+        *next += 1;
+        Ok(buffers[*next - 1])
+    }
+
+    unsafe fn reset(&mut self, device: &Device) -> Result<()> {
+        device.reset_command_pool(self.pool, CommandPoolResetFlags::empty())?;
+        self.next_primary = 0;
+        self.next_secondary = 0;
+        Ok(())
+    }
+}
+
+/// Owns the command pools of every frame in flight and every recording
+/// thread, plus a pool for one-shot uploads.
+///
+/// The pools of a frame are reset by `begin_frame`, so buffers handed out
+/// for a frame are only valid until that frame slot comes around again.
+#[derive(Clone, Debug, Default)]
+pub struct CommandContext {
+    queue: Queue,
+    threads: usize,
+    pools: Vec<Arc<Mutex<PoolState>>>,
+    immediate: Arc<Mutex<PoolState>>,
+    immediate_fence: Fence,
+}
+
+impl CommandContext {
+    /// # Safety
+    ///
+    /// `queue` must be a queue of `queue_family` on `device`.
+    pub unsafe fn new(
+        device: &Device,
+        queue_family: u32,
+        queue: Queue,
+        frames: usize,
+        threads: usize,
+    ) -> Result<Self> {
+        let threads = threads.max(1);
+        let pools = (0..frames * threads)
+            .map(|_| {
+                PoolState::new(device, queue_family, CommandPoolCreateFlags::TRANSIENT)
+                    .map(|p| Arc::new(Mutex::new(p)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let immediate = PoolState::new(device, queue_family, CommandPoolCreateFlags::TRANSIENT)?;
+        let immediate_fence = device.create_fence(&FenceCreateInfo::builder(), None)?;
+
+        Ok(Self {
+            queue,
+            threads,
+            pools,
+            immediate: Arc::new(Mutex::new(immediate)),
+            immediate_fence,
+        })
+    }
+
+    /// Resets every pool of `frame`.
+    ///
+    /// # Safety
+    ///
+    /// The frame's previous submission must have finished.
+    pub unsafe fn begin_frame(&self, device: &Device, frame: usize) -> Result<()> {
+        for thread in 0..self.threads {
+            self.pool(frame, thread)?.lock().unwrap().reset(device)?;
+        }
+
+        Ok(())
+    }
+
+    /// A primary command buffer from the pool of `frame` and `thread`.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be the device the context was created with. The command
+    /// buffer is only valid until `begin_frame` is called for `frame` again.
+    pub unsafe fn primary(
+        &self,
+        device: &Device,
+        frame: usize,
+        thread: usize,
+    ) -> Result<CommandBuffer> {
+        self.pool(frame, thread)?
+            .lock()
+            .unwrap()
+            .allocate(device, CommandBufferLevel::PRIMARY)
+    }
+
+    /// A secondary command buffer from the pool of `frame` and `thread`.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be the device the context was created with. The command
+    /// buffer is only valid until `begin_frame` is called for `frame` again.
+    pub unsafe fn secondary(
+        &self,
+        device: &Device,
+        frame: usize,
+        thread: usize,
+    ) -> Result<CommandBuffer> {
+        self.pool(frame, thread)?
+            .lock()
+            .unwrap()
+            .allocate(device, CommandBufferLevel::SECONDARY)
+    }
+
+    /// Records commands with `f`, submits them and waits for them to finish.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be the device the context was created with, and `f` must
+    /// only record into the command buffer it is given.
+    pub unsafe fn immediate_submit<F>(&self, device: &Device, f: F) -> Result<()>
+    where
+        F: FnOnce(CommandBuffer) -> Result<()>,
+    {
+        let mut immediate = self.immediate.lock().unwrap();
+        let command_buffer = immediate.allocate(device, CommandBufferLevel::PRIMARY)?;
+
+        let info =
+            CommandBufferBeginInfo::builder().flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(command_buffer, &info)?;
+        let recorded = f(command_buffer);
+        device.end_command_buffer(command_buffer)?;
+
+        if recorded.is_ok() {
+            let command_buffers = &[command_buffer];
+            let info = SubmitInfo::builder().command_buffers(command_buffers);
+
+            device.queue_submit(self.queue, &[info], self.immediate_fence)?;
+            device.wait_for_fences(&[self.immediate_fence], true, u64::MAX)?;
+            device.reset_fences(&[self.immediate_fence])?;
+        }
+
+        immediate.reset(device)?;
+        recorded
+    }
+
+    /// # Safety
+    ///
+    /// `device` must be the device the context was created with, and no
+    /// command buffer of the context may still be pending.
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        for pool in self.pools.drain(..).chain([self.immediate.clone()]) {
+            device.destroy_command_pool(pool.lock().unwrap().pool, None);
+        }
+
+        device.destroy_fence(self.immediate_fence, None);
+    }
+
+    fn pool(&self, frame: usize, thread: usize) -> Result<&Mutex<PoolState>> {
+        if thread >= self.threads {
+            return Err(anyhow!(
+                "Thread {} has no command pool ({} threads).",
+                thread,
+                self.threads
+            ));
+        }
+
+        self.pools
+            .get(frame * self.threads + thread)
+            .map(|p| p.as_ref())
+            .ok_or_else(|| anyhow!("Frame {} has no command pools.", frame))
+    }
+}
@@ -0,0 +1,195 @@
+//! Compiles WGSL and GLSL to SPIR-V with naga.
+//!
+//! This module only depends on naga and std because the build script
+//! includes it to precompile `shaders/`.
+
+use naga::{
+    back::spv,
+    front::{glsl, wgsl},
+    valid::{Capabilities, ValidationFlags, Validator},
+    Module, ShaderStage, SourceLocation,
+};
+use std::{error::Error, ffi::CStr, fmt, path::Path};
+
+/// The entry point every compiled module exposes, whatever it was called
+/// in the source.
+pub const ENTRY_POINT: &CStr = c"main";
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Language {
+    Wgsl,
+    Glsl,
+}
+
+impl Language {
+    /// The language of a shader source file, or `None` for SPIR-V and
+    /// unknown files.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "wgsl" => Some(Self::Wgsl),
+            "glsl" | "vert" | "frag" | "comp" => Some(Self::Glsl),
+            _ => None,
+        }
+    }
+}
+
+/// The GLSL file extension of `stage`, also used to name precompiled
+/// shaders.
+pub fn stage_extension(stage: ShaderStage) -> &'static str {
+    match stage {
+        ShaderStage::Vertex => "vert",
+        ShaderStage::Fragment => "frag",
+        _ => "comp",
+    }
+}
+
+/// A compile error at a position in a shader source file.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub path: String,
+    pub line: u32,
+    pub column: u32,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(path: &Path, location: Option<SourceLocation>, message: String) -> Self {
+        let location = location.unwrap_or(SourceLocation {
+            line_number: 1,
+            line_position: 1,
+            offset: 0,
+            length: 0,
+        });
+
+        Self {
+            path: path.display().to_string(),
+            line: location.line_number,
+            column: location.line_position,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.path, self.line, self.column, self.message
+        )
+    }
+}
+
+/// Compiles the `stage` entry point of `source` to SPIR-V. `path` is only
+/// used for diagnostics.
+pub fn compile(
+    path: &Path,
+    source: &str,
+    language: Language,
+    stage: ShaderStage,
+) -> Result<Vec<u32>, Vec<Diagnostic>> {
+    let mut module = match language {
+        Language::Wgsl => wgsl::parse_str(source).map_err(|e| {
+            vec![Diagnostic::new(
+                path,
+                e.location(source),
+                e.message().into(),
+            )]
+        })?,
+        Language::Glsl => glsl::Frontend::default()
+            .parse(&glsl::Options::from(stage), source)
+            .map_err(|e| {
+                e.errors
+                    .iter()
+                    .map(|e| Diagnostic::new(path, e.location(source), e.kind.to_string()))
+                    .collect::<Vec<_>>()
+            })?,
+    };
+
+    let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|e| vec![Diagnostic::new(path, e.location(source), error_chain(&e))])?;
+
+    let entry_point = select_entry_point(&mut module, stage).ok_or_else(|| {
+        vec![Diagnostic::new(
+            path,
+            None,
+            format!("no {:?} entry point", stage),
+        )]
+    })?;
+
+    let mut options = spv::Options::default();
+    if language == Language::Glsl {
+        // GLSL for Vulkan is already written in Vulkan's coordinate space.
+        options
+            .flags
+            .remove(spv::WriterFlags::ADJUST_COORDINATE_SPACE);
+    }
+
+    let pipeline_options = spv::PipelineOptions {
+        shader_stage: stage,
+        entry_point,
+    };
+
+    spv::write_vec(&module, &info, &options, Some(&pipeline_options))
+        .map_err(|e| vec![Diagnostic::new(path, None, e.to_string())])
+}
+
+/// Renames the first entry point of `stage` to `ENTRY_POINT`, so WGSL
+/// files can hold several stages with different entry point names.
+fn select_entry_point(module: &mut Module, stage: ShaderStage) -> Option<String> {
+    let entry_point = module.entry_points.iter_mut().find(|e| e.stage == stage)?;
+    entry_point.name = ENTRY_POINT.to_string_lossy().into_owned();
+    Some(entry_point.name.clone())
+}
+
+fn error_chain(error: &dyn Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wgsl_syntax_error_location() {
+        let source = "@vertex\nfn vs() -> @builtin(position) vec4<f32> {\n    let x = 1.0\n    return vec4(x);\n}\n";
+        let diagnostics = compile(
+            Path::new("bad.wgsl"),
+            source,
+            Language::Wgsl,
+            ShaderStage::Vertex,
+        )
+        .unwrap_err();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "bad.wgsl:4:5: expected `;`, found \"return\""
+        );
+    }
+
+    #[test]
+    fn missing_stage() {
+        let source = "@compute @workgroup_size(1)\nfn cs() {}\n";
+        let diagnostics = compile(
+            Path::new("compute.wgsl"),
+            source,
+            Language::Wgsl,
+            ShaderStage::Fragment,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "compute.wgsl:1:1: no Fragment entry point"
+        );
+    }
+}
@@ -0,0 +1,671 @@
+use log::{info, warn};
+
+use crate::loader::LOADER_PATH_ENV;
+use crate::pipeline::ShaderSource;
+use crate::pipeline_cache;
+use std::{env, path::PathBuf};
+use vulkanalia::{
+    vk::{
+        DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT, Format, PresentModeKHR,
+    },
+    Version,
+};
+
+/// Environment variable used to force a physical device by index or name.
+pub const DEVICE_ENV: &str = "VK_TUTORIAL_DEVICE";
+/// Environment variable with the path to an ICD manifest (e.g. a bundled
+/// lavapipe `lvp_icd.json`) the loader should use instead of the system ones.
+pub const ICD_ENV: &str = "VK_TUTORIAL_ICD";
+/// Environment variable capping the requested Vulkan version, e.g. `1.2`.
+pub const API_VERSION_ENV: &str = "VK_TUTORIAL_API_VERSION";
+/// Environment variable selecting the validation level (`off`, `standard` or `extended`).
+pub const VALIDATION_ENV: &str = "VK_TUTORIAL_VALIDATION";
+/// Environment variable overriding the messenger severities, e.g. `warning,error`.
+pub const VALIDATION_SEVERITY_ENV: &str = "VK_TUTORIAL_VALIDATION_SEVERITY";
+/// Environment variable overriding the messenger types, e.g. `validation,performance`.
+pub const VALIDATION_TYPES_ENV: &str = "VK_TUTORIAL_VALIDATION_TYPES";
+/// Environment variable selecting what a validation error does (`log` or `fail`).
+pub const VALIDATION_ERRORS_ENV: &str = "VK_TUTORIAL_VALIDATION_ERRORS";
+/// Environment variable with a comma-separated list of validation features, e.g.
+/// `best-practices,synchronization`.
+pub const VALIDATION_FEATURES_ENV: &str = "VK_TUTORIAL_VALIDATION_FEATURES";
+/// Environment variable with a comma-separated list of validation message IDs
+/// (names or numbers) to suppress.
+pub const VALIDATION_MUTE_ENV: &str = "VK_TUTORIAL_VALIDATION_MUTE";
+/// Environment variable with a comma-separated list of extra layers to enable.
+pub const LAYERS_ENV: &str = "VK_TUTORIAL_LAYERS";
+
+/// Selects a physical device either by its enumeration index or by a
+/// case-insensitive substring of its name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl DeviceSelector {
+    pub fn parse(value: &str) -> Self {
+        match value.trim().parse::<usize>() {
+            Ok(index) => Self::Index(index),
+            Err(_) => Self::Name(value.trim().to_lowercase()),
+        }
+    }
+
+    pub fn matches(&self, index: usize, name: &str) -> bool {
+        match self {
+            Self::Index(i) => *i == index,
+            Self::Name(n) => name.to_lowercase().contains(n.as_str()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValidationLevel {
+    Off,
+    /// Warnings and errors from the Khronos validation layer.
+    Standard,
+    /// Every severity and message type the validation layer reports.
+    Extended,
+}
+
+impl ValidationLevel {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "off" | "none" | "0" => Some(Self::Off),
+            "standard" | "on" | "1" => Some(Self::Standard),
+            "extended" | "full" | "2" => Some(Self::Extended),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ValidationErrorPolicy {
+    /// Log validation errors and keep rendering.
+    #[default]
+    Log,
+    /// Fail the next `App::render` call after a validation error.
+    FailRender,
+}
+
+impl ValidationErrorPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "log" => Some(Self::Log),
+            "fail" => Some(Self::FailRender),
+            _ => None,
+        }
+    }
+}
+
+/// Optional checks of the validation layer enabled through
+/// `VK_EXT_validation_features`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationFeatures {
+    pub gpu_assisted: bool,
+    pub best_practices: bool,
+    pub synchronization: bool,
+    /// Forwards `debugPrintfEXT` output from shaders as INFO messages.
+    pub debug_printf: bool,
+}
+
+impl ValidationFeatures {
+    pub fn any(&self) -> bool {
+        self.gpu_assisted || self.best_practices || self.synchronization || self.debug_printf
+    }
+
+    fn parse(value: &str) -> Self {
+        let mut features = Self::default();
+
+        for name in split_list(value) {
+            match name.to_lowercase().as_str() {
+                "gpu-assisted" | "gpu" => features.gpu_assisted = true,
+                "best-practices" => features.best_practices = true,
+                "synchronization" | "sync" => features.synchronization = true,
+                "debug-printf" | "printf" => features.debug_printf = true,
+                "none" => features = Self::default(),
+                _ => warn!("Ignoring unknown validation feature `{}`.", name),
+            }
+        }
+
+        features
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationConfig {
+    pub level: ValidationLevel,
+    pub severity: DebugUtilsMessageSeverityFlagsEXT,
+    pub types: DebugUtilsMessageTypeFlagsEXT,
+    pub error_policy: ValidationErrorPolicy,
+    pub features: ValidationFeatures,
+    /// Message ID names or numbers that are dropped by the messenger.
+    pub muted_ids: Vec<String>,
+    /// Layers enabled in addition to the validation layer, even when
+    /// validation is off.
+    pub extra_layers: Vec<String>,
+}
+
+impl ValidationConfig {
+    /// The default messenger masks and features for `level`. Extended
+    /// validation turns on best-practices and synchronization checks.
+    pub fn new(level: ValidationLevel) -> Self {
+        let (severity, types) = match level {
+            ValidationLevel::Off => (
+                DebugUtilsMessageSeverityFlagsEXT::empty(),
+                DebugUtilsMessageTypeFlagsEXT::empty(),
+            ),
+            ValidationLevel::Standard => (
+                DebugUtilsMessageSeverityFlagsEXT::WARNING
+                    | DebugUtilsMessageSeverityFlagsEXT::ERROR,
+                DebugUtilsMessageTypeFlagsEXT::GENERAL | DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            ),
+            ValidationLevel::Extended => (
+                DebugUtilsMessageSeverityFlagsEXT::all(),
+                DebugUtilsMessageTypeFlagsEXT::all(),
+            ),
+        };
+
+        let features = ValidationFeatures {
+            best_practices: level == ValidationLevel::Extended,
+            synchronization: level == ValidationLevel::Extended,
+            ..Default::default()
+        };
+
+        Self {
+            level,
+            severity,
+            types,
+            error_policy: ValidationErrorPolicy::default(),
+            features,
+            muted_ids: vec![],
+            extra_layers: vec![],
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.level != ValidationLevel::Off
+    }
+
+    fn from_args(args: &[String], var: &impl Fn(&str) -> Option<String>) -> Self {
+        let level = arg_value(args, "--validation")
+            .or_else(|| var(VALIDATION_ENV))
+            .and_then(|value| {
+                let level = ValidationLevel::parse(&value);
+                if level.is_none() {
+                    warn!("Ignoring unknown validation level `{}`.", value);
+                }
+                level
+            });
+
+        let mut config = Self::new(level.unwrap_or(Self::default().level));
+
+        if let Some(value) =
+            arg_value(args, "--validation-severity").or_else(|| var(VALIDATION_SEVERITY_ENV))
+        {
+            config.severity = parse_severity(&value);
+        }
+
+        if let Some(value) =
+            arg_value(args, "--validation-types").or_else(|| var(VALIDATION_TYPES_ENV))
+        {
+            config.types = parse_types(&value);
+        }
+
+        if let Some(value) =
+            arg_value(args, "--validation-errors").or_else(|| var(VALIDATION_ERRORS_ENV))
+        {
+            match ValidationErrorPolicy::parse(&value) {
+                Some(policy) => config.error_policy = policy,
+                None => warn!("Ignoring unknown validation error policy `{}`.", value),
+            }
+        }
+
+        if let Some(value) =
+            arg_value(args, "--validation-features").or_else(|| var(VALIDATION_FEATURES_ENV))
+        {
+            config.features = ValidationFeatures::parse(&value);
+        }
+
+        config.muted_ids = arg_values(args, "--validation-mute");
+        if let Some(value) = var(VALIDATION_MUTE_ENV) {
+            config.muted_ids.extend(split_list(&value));
+        }
+
+        config.extra_layers = arg_values(args, "--layer");
+        if let Some(value) = var(LAYERS_ENV) {
+            config.extra_layers.extend(split_list(&value));
+        }
+
+        config
+    }
+}
+
+impl Default for ValidationConfig {
+    /// Validation is on in debug builds and off in release builds.
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            Self::new(ValidationLevel::Standard)
+        } else {
+            Self::new(ValidationLevel::Off)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AppConfig {
+    pub device: Option<DeviceSelector>,
+    /// An explicit Vulkan loader library (`--loader PATH`).
+    pub loader_path: Option<PathBuf>,
+    /// An ICD manifest to use instead of the installed drivers (`--icd PATH`).
+    pub icd: Option<PathBuf>,
+    /// The highest Vulkan version requested from the loader (`--api-version 1.2`).
+    pub api_version: Version,
+    pub validation: ValidationConfig,
+    /// Render into an offscreen image instead of a window (`--headless`).
+    pub headless: bool,
+    /// Size of the offscreen image in headless mode (`--size WIDTHxHEIGHT`).
+    pub size: (u32, u32),
+    /// Where to write the rendered frame in headless mode (`--capture PATH`).
+    pub capture: Option<PathBuf>,
+    /// Color the frame is cleared to (`--clear-color R,G,B,A`).
+    pub clear_color: [f32; 4],
+    /// Swapchain formats in order of preference (`--surface-format
+    /// B8G8R8A8_SRGB,R8G8B8A8_SRGB`). The first supported one is used.
+    pub surface_formats: Vec<Format>,
+    /// Present modes in order of preference (`--present-mode mailbox,fifo`).
+    /// FIFO is used if none of them is supported.
+    pub present_modes: Vec<PresentModeKHR>,
+    /// How many frames the CPU may record ahead of the GPU
+    /// (`--frames-in-flight N`).
+    pub frames_in_flight: usize,
+    /// Shaders of the pipeline drawn each frame (`--vertex-shader` and
+    /// `--fragment-shader`), as SPIR-V, WGSL or GLSL files or
+    /// `precompiled:NAME`. Without both, frames are only cleared.
+    pub vertex_shader: Option<ShaderSource>,
+    pub fragment_shader: Option<ShaderSource>,
+    /// Rebuild the pipeline when its shader files change
+    /// (`--watch-shaders`).
+    pub watch_shaders: bool,
+    /// Where the pipeline cache is kept between runs (`--pipeline-cache
+    /// PATH`, or `--no-pipeline-cache` to disable it). Defaults to the user
+    /// cache directory.
+    pub pipeline_cache: Option<PathBuf>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            device: None,
+            loader_path: None,
+            icd: None,
+            api_version: Version::new(1, 3, 0),
+            validation: ValidationConfig::default(),
+            headless: false,
+            size: (1024, 768),
+            capture: None,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            surface_formats: vec![Format::B8G8R8A8_SRGB, Format::R8G8B8A8_SRGB],
+            present_modes: vec![PresentModeKHR::MAILBOX, PresentModeKHR::FIFO],
+            frames_in_flight: 2,
+            vertex_shader: None,
+            fragment_shader: None,
+            watch_shaders: false,
+            pipeline_cache: pipeline_cache::default_path(),
+        }
+    }
+}
+
+impl AppConfig {
+    /// Reads the configuration from the process arguments and environment.
+    /// Command-line arguments take precedence over environment variables.
+    pub fn from_env() -> Self {
+        let args = env::args().skip(1).collect::<Vec<_>>();
+        Self::from_args(&args, |key| env::var(key).ok())
+    }
+
+    pub fn from_args(args: &[String], var: impl Fn(&str) -> Option<String>) -> Self {
+        let device = arg_value(args, "--device")
+            .or_else(|| var(DEVICE_ENV))
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| DeviceSelector::parse(&value));
+
+        let loader_path = arg_value(args, "--loader")
+            .or_else(|| var(LOADER_PATH_ENV))
+            .map(PathBuf::from);
+
+        let icd = arg_value(args, "--icd")
+            .or_else(|| var(ICD_ENV))
+            .map(PathBuf::from);
+
+        let api_version = arg_value(args, "--api-version")
+            .or_else(|| var(API_VERSION_ENV))
+            .and_then(|value| {
+                let version = parse_version(&value);
+                if version.is_none() {
+                    warn!("Ignoring invalid API version `{}`.", value);
+                }
+                version
+            })
+            .unwrap_or(Self::default().api_version);
+
+        let validation = ValidationConfig::from_args(args, &var);
+
+        let headless = args.iter().any(|a| a == "--headless");
+
+        let size = arg_value(args, "--size")
+            .and_then(|value| parse_size(&value))
+            .unwrap_or(Self::default().size);
+
+        let capture = arg_value(args, "--capture").map(PathBuf::from);
+
+        let clear_color = arg_value(args, "--clear-color")
+            .and_then(|value| parse_color(&value))
+            .unwrap_or(Self::default().clear_color);
+
+        let surface_formats = arg_value(args, "--surface-format")
+            .map(|value| parse_list(&value, "surface format", parse_format))
+            .filter(|formats| !formats.is_empty())
+            .unwrap_or(Self::default().surface_formats);
+
+        let present_modes = arg_value(args, "--present-mode")
+            .map(|value| parse_list(&value, "present mode", parse_present_mode))
+            .filter(|modes| !modes.is_empty())
+            .unwrap_or(Self::default().present_modes);
+
+        let frames_in_flight = arg_value(args, "--frames-in-flight")
+            .and_then(|value| {
+                let frames = value.parse::<usize>().ok().filter(|n| *n > 0);
+                if frames.is_none() {
+                    warn!("Ignoring invalid frames in flight `{}`.", value);
+                }
+                frames
+            })
+            .unwrap_or(Self::default().frames_in_flight);
+
+        let vertex_shader = arg_value(args, "--vertex-shader").map(|v| ShaderSource::parse(&v));
+        let fragment_shader = arg_value(args, "--fragment-shader").map(|v| ShaderSource::parse(&v));
+
+        let watch_shaders = args.iter().any(|a| a == "--watch-shaders");
+
+        let pipeline_cache = if args.iter().any(|a| a == "--no-pipeline-cache") {
+            None
+        } else {
+            arg_value(args, "--pipeline-cache")
+                .map(PathBuf::from)
+                .or(Self::default().pipeline_cache)
+        };
+
+        Self {
+            device,
+            loader_path,
+            icd,
+            api_version,
+            validation,
+            headless,
+            size,
+            capture,
+            clear_color,
+            surface_formats,
+            present_modes,
+            frames_in_flight,
+            vertex_shader,
+            fragment_shader,
+            watch_shaders,
+            pipeline_cache,
+        }
+    }
+
+    /// Points the Vulkan loader at the configured ICD manifest. This must
+    /// happen before the loader library is opened.
+    pub fn apply_icd_override(&self) {
+        if let Some(icd) = &self.icd {
+            info!("Using ICD manifest `{}`.", icd.display());
+            // `VK_ICD_FILENAMES` is the name understood by loaders older
+            // than 1.3.207.
+            env::set_var("VK_DRIVER_FILES", icd);
+            env::set_var("VK_ICD_FILENAMES", icd);
+        }
+    }
+}
+
+/// Returns the value of the first `--name value` or `--name=value`.
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    arg_values(args, name).into_iter().next()
+}
+
+/// Returns the values of every `--name value` or `--name=value`.
+fn arg_values(args: &[String], name: &str) -> Vec<String> {
+    let mut values = vec![];
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == name {
+            values.extend(iter.next().cloned());
+        } else if let Some(value) = arg.strip_prefix(name).and_then(|a| a.strip_prefix('=')) {
+            values.push(value.to_string());
+        }
+    }
+
+    values
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+fn parse_severity(value: &str) -> DebugUtilsMessageSeverityFlagsEXT {
+    split_list(value).fold(DebugUtilsMessageSeverityFlagsEXT::empty(), |mask, name| {
+        mask | match name.to_lowercase().as_str() {
+            "verbose" => DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+            "info" => DebugUtilsMessageSeverityFlagsEXT::INFO,
+            "warning" => DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            "error" => DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            "all" => DebugUtilsMessageSeverityFlagsEXT::all(),
+            _ => {
+                warn!("Ignoring unknown validation severity `{}`.", name);
+                DebugUtilsMessageSeverityFlagsEXT::empty()
+            }
+        }
+    })
+}
+
+fn parse_types(value: &str) -> DebugUtilsMessageTypeFlagsEXT {
+    split_list(value).fold(DebugUtilsMessageTypeFlagsEXT::empty(), |mask, name| {
+        mask | match name.to_lowercase().as_str() {
+            "general" => DebugUtilsMessageTypeFlagsEXT::GENERAL,
+            "validation" => DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            "performance" => DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            "all" => DebugUtilsMessageTypeFlagsEXT::all(),
+            _ => {
+                warn!("Ignoring unknown validation message type `{}`.", name);
+                DebugUtilsMessageTypeFlagsEXT::empty()
+            }
+        }
+    })
+}
+
+fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.split_once('x')?;
+    let width = width.trim().parse().ok().filter(|w| *w > 0)?;
+    let height = height.trim().parse().ok().filter(|h| *h > 0)?;
+    Some((width, height))
+}
+
+fn parse_color(value: &str) -> Option<[f32; 4]> {
+    let components = value
+        .split(',')
+        .map(|c| c.trim().parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    components.try_into().ok()
+}
+
+/// Parses `MAJOR.MINOR` or `MAJOR.MINOR.PATCH`.
+fn parse_version(value: &str) -> Option<Version> {
+    let parts = value
+        .trim()
+        .split('.')
+        .map(|p| p.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    match parts[..] {
+        [major, minor] => Some(Version::new(major, minor, 0)),
+        [major, minor, patch] => Some(Version::new(major, minor, patch)),
+        _ => None,
+    }
+}
+
+fn parse_list<T>(value: &str, kind: &str, parse: impl Fn(&str) -> Option<T>) -> Vec<T> {
+    split_list(value)
+        .filter_map(|item| {
+            let parsed = parse(&item);
+            if parsed.is_none() {
+                warn!("Ignoring unknown {} `{}`.", kind, item);
+            }
+            parsed
+        })
+        .collect()
+}
+
+fn parse_format(value: &str) -> Option<Format> {
+    match value.to_uppercase().as_str() {
+        "B8G8R8A8_SRGB" => Some(Format::B8G8R8A8_SRGB),
+        "B8G8R8A8_UNORM" => Some(Format::B8G8R8A8_UNORM),
+        "R8G8B8A8_SRGB" => Some(Format::R8G8B8A8_SRGB),
+        "R8G8B8A8_UNORM" => Some(Format::R8G8B8A8_UNORM),
+        _ => None,
+    }
+}
+
+fn parse_present_mode(value: &str) -> Option<PresentModeKHR> {
+    match value.to_lowercase().as_str() {
+        "immediate" => Some(PresentModeKHR::IMMEDIATE),
+        "mailbox" => Some(PresentModeKHR::MAILBOX),
+        "fifo" => Some(PresentModeKHR::FIFO),
+        "fifo_relaxed" | "fifo-relaxed" => Some(PresentModeKHR::FIFO_RELAXED),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn arg_values_both_forms() {
+        let args = args(&[
+            "--layer",
+            "a",
+            "--headless",
+            "--layer=b",
+            "--layers=c",
+            "--layer",
+        ]);
+        assert_eq!(arg_values(&args, "--layer"), vec!["a", "b"]);
+        assert_eq!(arg_value(&args, "--layer").as_deref(), Some("a"));
+        assert_eq!(arg_value(&args, "--device"), None);
+    }
+
+    #[test]
+    fn args_take_precedence_over_env() {
+        let env = |key: &str| match key {
+            DEVICE_ENV => Some("llvmpipe".to_string()),
+            VALIDATION_ENV => Some("off".to_string()),
+            API_VERSION_ENV => Some("1.1".to_string()),
+            _ => None,
+        };
+
+        let config = AppConfig::from_args(&args(&["--device=1", "--validation", "extended"]), env);
+        assert_eq!(config.device, Some(DeviceSelector::Index(1)));
+        assert_eq!(config.validation.level, ValidationLevel::Extended);
+        assert_eq!(config.api_version, Version::new(1, 1, 0));
+
+        let config = AppConfig::from_args(&[], env);
+        assert_eq!(config.device, Some(DeviceSelector::Name("llvmpipe".into())));
+        assert_eq!(config.validation.level, ValidationLevel::Off);
+    }
+
+    #[test]
+    fn validation_level_parse() {
+        assert_eq!(ValidationLevel::parse("off"), Some(ValidationLevel::Off));
+        assert_eq!(ValidationLevel::parse("0"), Some(ValidationLevel::Off));
+        assert_eq!(
+            ValidationLevel::parse(" ON "),
+            Some(ValidationLevel::Standard)
+        );
+        assert_eq!(ValidationLevel::parse("1"), Some(ValidationLevel::Standard));
+        assert_eq!(
+            ValidationLevel::parse("Full"),
+            Some(ValidationLevel::Extended)
+        );
+        assert_eq!(ValidationLevel::parse("2"), Some(ValidationLevel::Extended));
+        assert_eq!(ValidationLevel::parse("verbose"), None);
+    }
+
+    #[test]
+    fn severity_and_type_masks() {
+        assert_eq!(
+            parse_severity("warning, ERROR,,bogus"),
+            DebugUtilsMessageSeverityFlagsEXT::WARNING | DebugUtilsMessageSeverityFlagsEXT::ERROR
+        );
+        assert_eq!(
+            parse_severity("info,all"),
+            DebugUtilsMessageSeverityFlagsEXT::all()
+        );
+        assert_eq!(
+            parse_types("validation,performance"),
+            DebugUtilsMessageTypeFlagsEXT::VALIDATION | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
+        );
+        assert_eq!(parse_types(""), DebugUtilsMessageTypeFlagsEXT::empty());
+    }
+
+    #[test]
+    fn muted_ids_combine_args_and_env() {
+        let env = |key: &str| (key == VALIDATION_MUTE_ENV).then(|| "c, 0x1234".to_string());
+        let args = args(&["--validation-mute", "a", "--validation-mute=b"]);
+
+        let config = ValidationConfig::from_args(&args, &env);
+        assert_eq!(config.muted_ids, vec!["a", "b", "c", "0x1234"]);
+
+        let config = ValidationConfig::from_args(&args, &no_env);
+        assert_eq!(config.muted_ids, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn device_selector_by_index() {
+        let selector = DeviceSelector::parse("1");
+        assert_eq!(selector, DeviceSelector::Index(1));
+        assert!(selector.matches(1, "llvmpipe (LLVM 15.0.6, 256 bits)"));
+        assert!(!selector.matches(0, "llvmpipe (LLVM 15.0.6, 256 bits)"));
+    }
+
+    #[test]
+    fn device_selector_by_name() {
+        let selector = DeviceSelector::parse("LLVMpipe");
+        assert_eq!(selector, DeviceSelector::Name("llvmpipe".into()));
+        assert!(selector.matches(3, "llvmpipe (LLVM 15.0.6, 256 bits)"));
+        assert!(selector.matches(0, "LLVMPIPE"));
+        assert!(!selector.matches(0, "NVIDIA GeForce RTX 4090"));
+    }
+
+    #[test]
+    fn device_selector_trims_whitespace() {
+        assert_eq!(DeviceSelector::parse(" 2\n"), DeviceSelector::Index(2));
+
+        let selector = DeviceSelector::parse("  llvmpipe ");
+        assert_eq!(selector, DeviceSelector::Name("llvmpipe".into()));
+        assert!(selector.matches(0, "llvmpipe (LLVM 15.0.6, 256 bits)"));
+    }
+}
@@ -0,0 +1,55 @@
+use thiserror::Error;
+use vulkanalia::vk::{ErrorCode, Result as VkResult};
+
+/// The failures callers of `App` can tell apart. Anything else is reported as
+/// `Vulkan` (for an error code without a more specific meaning) or `Other`.
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error(
+        "Vulkan loader not found. Install the Vulkan loader (libvulkan) or set \
+         VK_TUTORIAL_LOADER_PATH. Tried:\n  {}",
+        .0.join("\n  ")
+    )]
+    LoaderNotFound(Vec<String>),
+    #[error("Missing required instance layers: {}.", .0.join(", "))]
+    MissingLayer(Vec<String>),
+    #[error("Missing required instance extensions: {}.", .0.join(", "))]
+    MissingExtension(Vec<String>),
+    #[error("No suitable physical device: {}", .0.join("; "))]
+    NoSuitableDevice(Vec<String>),
+    #[error("Surface lost ({0}).")]
+    SurfaceLost(VkResult),
+    #[error("Swapchain out of date ({0}).")]
+    OutOfDate(VkResult),
+    #[error("Validation error: {0}")]
+    Validation(String),
+    #[error("Vulkan call failed ({0}).")]
+    Vulkan(VkResult),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<ErrorCode> for AppError {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::SURFACE_LOST_KHR => Self::SurfaceLost(code.into()),
+            ErrorCode::OUT_OF_DATE_KHR => Self::OutOfDate(code.into()),
+            _ => Self::Vulkan(code.into()),
+        }
+    }
+}
+
+/// Recovers typed errors that were propagated through `anyhow`.
+impl From<anyhow::Error> for AppError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<AppError>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+
+        match error.downcast::<ErrorCode>() {
+            Ok(code) => code.into(),
+            Err(error) => Self::Other(error),
+        }
+    }
+}
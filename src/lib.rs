@@ -0,0 +1,14 @@
+pub mod allocator;
+pub mod app;
+pub mod capture;
+pub mod commands;
+pub mod compile;
+pub mod config;
+pub mod error;
+pub mod loader;
+pub mod pipeline;
+pub mod pipeline_cache;
+pub mod reflect;
+pub mod requirements;
+pub mod validation;
+pub mod watch;
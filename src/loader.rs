@@ -0,0 +1,77 @@
+use log::{debug, info};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+use vulkanalia::{
+    loader::{LibloadingLoader, LIBRARY},
+    Entry,
+};
+
+use crate::error::AppError;
+
+/// Environment variable with an explicit path to the Vulkan loader library.
+pub const LOADER_PATH_ENV: &str = "VK_TUTORIAL_LOADER_PATH";
+
+/// Library names tried after the platform default, for systems where only
+/// the unversioned or an alternative name is installed.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const FALLBACK_LIBRARIES: &[&str] = &[
+    "libvulkan.1.dylib",
+    "/usr/local/lib/libvulkan.dylib",
+    "/opt/homebrew/lib/libvulkan.dylib",
+    "libMoltenVK.dylib",
+];
+#[cfg(windows)]
+const FALLBACK_LIBRARIES: &[&str] = &[];
+#[cfg(not(any(target_os = "macos", target_os = "ios", windows)))]
+const FALLBACK_LIBRARIES: &[&str] = &["libvulkan.so"];
+
+/// The loader libraries to try, in order: the explicit override, the
+/// platform default, the fallback names and finally a copy next to the
+/// executable.
+pub fn candidate_paths(override_path: Option<&Path>) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    paths.extend(override_path.map(Path::to_path_buf));
+    paths.push(LIBRARY.into());
+    paths.extend(FALLBACK_LIBRARIES.iter().map(PathBuf::from));
+
+    if let Some(dir) = env::current_exe()
+        .ok()
+        .and_then(|e| e.parent().map(Path::to_path_buf))
+    {
+        paths.push(dir.join(LIBRARY));
+    }
+
+    paths
+}
+
+/// Loads the first Vulkan loader that can be found. On failure the error
+/// lists every path that was tried and why it was rejected.
+///
+/// # Safety
+///
+/// Loading a library runs its initialization code, so any library found
+/// must be a genuine Vulkan loader.
+pub unsafe fn load_entry(override_path: Option<&Path>) -> Result<Entry, AppError> {
+    let mut attempts = vec![];
+
+    for path in candidate_paths(override_path) {
+        let result = LibloadingLoader::new(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|loader| Entry::new(loader).map_err(|e| e.to_string()));
+
+        match result {
+            Ok(entry) => {
+                info!("Loaded Vulkan loader `{}`.", path.display());
+                return Ok(entry);
+            }
+            Err(error) => {
+                debug!("Failed to load `{}`: {}", path.display(), error);
+                attempts.push(format!("`{}`: {}", path.display(), error));
+            }
+        }
+    }
+
+    Err(AppError::LoaderNotFound(attempts))
+}
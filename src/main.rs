@@ -1,22 +1,22 @@
-#![allow(
-    dead_code,
-    unused_variables,
-    clippy::too_many_arguments,
-    clippy::unnecessary_wraps
-)]
-mod app;
-
 use anyhow::{Ok, Result};
-use app::App;
+use vk_tutorial::{app::App, config::AppConfig};
 use winit::{
     dpi::LogicalSize,
-    event::{Event, WindowEvent},
+    event::{ElementState, Event, KeyEvent, WindowEvent},
     event_loop::EventLoop,
+    keyboard::{Key, NamedKey},
     window::WindowBuilder,
 };
 
+const SCREENSHOT_PATH: &str = "screenshot.png";
+
 fn main() -> Result<()> {
     pretty_env_logger::init();
+    let config = AppConfig::from_env();
+
+    if config.headless {
+        return run_headless(&config);
+    }
 
     // Window
     let event_loop = EventLoop::new()?;
@@ -26,7 +26,7 @@ fn main() -> Result<()> {
         .build(&event_loop)?;
 
     // App
-    let mut app = unsafe { App::create(&window)? };
+    let mut app = unsafe { App::create(&window, &config)? };
     event_loop.run(move |event, elwt| {
         match event {
             // Request a redraw when all events were processed.
@@ -37,6 +37,24 @@ fn main() -> Result<()> {
                     unsafe { app.render(&window) }.unwrap()
                 }
 
+                // Recreate the swapchain for the new window size.
+                WindowEvent::Resized(_) => app.window_resized(),
+
+                // Save a screenshot of the next frame.
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            logical_key: Key::Named(NamedKey::F12),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Err(e) = unsafe { app.capture_frame(SCREENSHOT_PATH) } {
+                        log::error!("Failed to capture screenshot: {}", e);
+                    }
+                }
+
                 // Destroy our Vulkan app.
                 WindowEvent::CloseRequested => {
                     elwt.exit();
@@ -52,3 +70,16 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+fn run_headless(config: &AppConfig) -> Result<()> {
+    unsafe {
+        let mut app = App::create_headless(config)?;
+        let result = app.render_offscreen().and_then(|_| match &config.capture {
+            Some(path) => app.capture_frame(path),
+            None => Result::Ok(()),
+        });
+
+        app.destroy();
+        Ok(result?)
+    }
+}
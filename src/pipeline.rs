@@ -0,0 +1,661 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use naga::ShaderStage;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use vulkanalia::{
+    prelude::v1_0::*,
+    vk::{
+        BlendFactor, BlendOp, ColorComponentFlags, CompareOp, CullModeFlags, DescriptorSetLayout,
+        DescriptorSetLayoutCreateInfo, DynamicState, Format, FrontFace, GraphicsPipelineCreateInfo,
+        Pipeline, PipelineCache, PipelineColorBlendAttachmentState,
+        PipelineColorBlendStateCreateInfo, PipelineDepthStencilStateCreateInfo,
+        PipelineDynamicStateCreateInfo, PipelineInputAssemblyStateCreateInfo, PipelineLayout,
+        PipelineLayoutCreateInfo, PipelineMultisampleStateCreateInfo,
+        PipelineRasterizationStateCreateInfo, PipelineRenderingCreateInfo,
+        PipelineShaderStageCreateInfo, PipelineVertexInputStateCreateInfo,
+        PipelineViewportStateCreateInfo, PolygonMode, PrimitiveTopology, PushConstantRange,
+        RenderPass, SampleCountFlags, ShaderModule, ShaderModuleCreateInfo, ShaderStageFlags,
+        VertexInputAttributeDescription, VertexInputBindingDescription,
+    },
+};
+
+use crate::compile::{self, stage_extension, Diagnostic, Language, ENTRY_POINT};
+use crate::reflect::{self, reflect_stage, PipelineReflection, ReflectError};
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+// `(name, stage, SPIR-V)` for every shader under `shaders/`, generated by the
+// build script. Empty without the `precompiled-shaders` feature.
+include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+
+#[derive(Debug, Error)]
+pub enum ShaderError {
+    #[error("failed to read shader `{}`: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("shader `{}` is {len} bytes, which is not a multiple of 4", .path.display())]
+    Misaligned { path: PathBuf, len: usize },
+    #[error("shader `{}` is not SPIR-V (magic number {magic:#010x})", .path.display())]
+    BadMagic { path: PathBuf, magic: u32 },
+    #[error("shader compilation failed:\n{}", .0.iter().map(|d| d.to_string()).collect::<Vec<_>>().join("\n"))]
+    Compile(Vec<Diagnostic>),
+    #[error("no precompiled {stage} shader `{name}`")]
+    NotPrecompiled { name: String, stage: &'static str },
+}
+
+/// Reads a SPIR-V module, checking its size and magic number.
+pub fn read_spirv(path: &Path) -> Result<Vec<u32>, ShaderError> {
+    let bytes = fs::read(path).map_err(|source| ShaderError::Io {
+        path: path.into(),
+        source,
+    })?;
+
+    if bytes.is_empty() || bytes.len() % 4 != 0 {
+        return Err(ShaderError::Misaligned {
+            path: path.into(),
+            len: bytes.len(),
+        });
+    }
+
+    let words = bytes
+        .chunks_exact(4)
+        .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+        .collect::<Vec<_>>();
+
+    if words[0] != SPIRV_MAGIC {
+        return Err(ShaderError::BadMagic {
+            path: path.into(),
+            magic: words[0],
+        });
+    }
+
+    Ok(words)
+}
+
+/// # Safety
+///
+/// `code` must be valid SPIR-V.
+pub unsafe fn create_shader_module(device: &Device, code: &[u32]) -> Result<ShaderModule> {
+    let info = ShaderModuleCreateInfo::builder()
+        .code_size(std::mem::size_of_val(code))
+        .code(code);
+
+    Ok(device.create_shader_module(&info, None)?)
+}
+
+/// Compiles a WGSL or GLSL file to SPIR-V for `stage`.
+pub fn compile_file(
+    path: &Path,
+    language: Language,
+    stage: ShaderStage,
+) -> Result<Vec<u32>, ShaderError> {
+    let source = fs::read_to_string(path).map_err(|source| ShaderError::Io {
+        path: path.into(),
+        source,
+    })?;
+
+    compile::compile(path, &source, language, stage).map_err(ShaderError::Compile)
+}
+
+/// A shader precompiled from `shaders/` by the build script. `name` is the
+/// path of the source relative to `shaders/`.
+pub fn precompiled(name: &str, stage: ShaderStage) -> Result<Vec<u32>, ShaderError> {
+    let stage = stage_extension(stage);
+
+    PRECOMPILED_SHADERS
+        .iter()
+        .find(|(n, s, _)| *n == name && *s == stage)
+        .map(|(_, _, bytes)| {
+            bytes
+                .chunks_exact(4)
+                .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+                .collect()
+        })
+        .ok_or_else(|| ShaderError::NotPrecompiled {
+            name: name.into(),
+            stage,
+        })
+}
+
+/// Where a shader stage comes from.
+#[derive(Clone, Debug)]
+pub enum ShaderSource {
+    /// A SPIR-V, WGSL or GLSL file on disk, told apart by its extension.
+    Path(PathBuf),
+    /// SPIR-V words already in memory.
+    Spirv(Vec<u32>),
+    /// A shader from `shaders/` compiled into the binary. Only available
+    /// with the `precompiled-shaders` feature.
+    Precompiled(String),
+}
+
+impl ShaderSource {
+    /// Parses a shader argument: a path, or `precompiled:NAME`.
+    pub fn parse(value: &str) -> Self {
+        match value.strip_prefix("precompiled:") {
+            Some(name) => Self::Precompiled(name.into()),
+            None => Self::Path(value.into()),
+        }
+    }
+
+    /// The file this source is read from, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Path(path) => Some(path),
+            Self::Spirv(_) | Self::Precompiled(_) => None,
+        }
+    }
+
+    pub fn load(&self, stage: ShaderStage) -> Result<Vec<u32>> {
+        match self {
+            Self::Path(path) => match Language::from_path(path) {
+                Some(language) => Ok(compile_file(path, language, stage)?),
+                None => Ok(read_spirv(path)?),
+            },
+            Self::Spirv(code) => Ok(code.clone()),
+            Self::Precompiled(name) => Ok(precompiled(name, stage)?),
+        }
+    }
+}
+
+/// What the pipeline renders into.
+#[derive(Clone, Debug)]
+pub enum RenderTarget {
+    RenderPass {
+        render_pass: RenderPass,
+        subpass: u32,
+    },
+    /// Dynamic rendering (Vulkan 1.3 or `VK_KHR_dynamic_rendering`, which
+    /// must be enabled on the device).
+    Dynamic {
+        color_formats: Vec<Format>,
+        depth_format: Format,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct GraphicsPipeline {
+    pub pipeline: Pipeline,
+    pub layout: PipelineLayout,
+    /// Descriptor set layouts derived from the shaders, owned by the
+    /// pipeline.
+    pub set_layouts: Vec<DescriptorSetLayout>,
+    pub reflection: Option<PipelineReflection>,
+}
+
+impl GraphicsPipeline {
+    /// # Safety
+    ///
+    /// `device` must be the device the pipeline was created with, and the
+    /// device must no longer use the pipeline.
+    pub unsafe fn destroy(&self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.layout, None);
+        self.set_layouts
+            .iter()
+            .for_each(|l| device.destroy_descriptor_set_layout(*l, None));
+    }
+}
+
+/// Creates a layout for every set up to the highest one used, leaving the
+/// sets in between empty.
+///
+/// # Safety
+///
+/// The caller owns the returned layouts and must destroy them with
+/// `device`.
+pub unsafe fn create_set_layouts(
+    device: &Device,
+    reflection: &PipelineReflection,
+) -> Result<Vec<DescriptorSetLayout>> {
+    let count = reflection.sets.keys().last().map_or(0, |s| s + 1);
+    let mut set_layouts = vec![];
+
+    for set in 0..count {
+        let bindings = reflection.sets.get(&set).map_or(&[][..], |b| &b[..]);
+        let info = DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+        match device.create_descriptor_set_layout(&info, None) {
+            Ok(set_layout) => set_layouts.push(set_layout),
+            Err(error) => {
+                set_layouts
+                    .iter()
+                    .for_each(|l| device.destroy_descriptor_set_layout(*l, None));
+                return Err(error.into());
+            }
+        }
+    }
+
+    Ok(set_layouts)
+}
+
+/// Builds a graphics pipeline with a vertex and a fragment stage. Viewport
+/// and scissor are dynamic, so the pipeline survives swapchain resizes.
+#[derive(Clone, Debug)]
+pub struct PipelineBuilder {
+    vertex_shader: Option<ShaderSource>,
+    fragment_shader: Option<ShaderSource>,
+    vertex_bindings: Vec<VertexInputBindingDescription>,
+    vertex_attributes: Vec<VertexInputAttributeDescription>,
+    topology: PrimitiveTopology,
+    polygon_mode: PolygonMode,
+    cull_mode: CullModeFlags,
+    front_face: FrontFace,
+    blend: bool,
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: CompareOp,
+    set_layouts: Vec<DescriptorSetLayout>,
+    push_constant_ranges: Vec<PushConstantRange>,
+    reflect: bool,
+    target: Option<RenderTarget>,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        Self {
+            vertex_shader: None,
+            fragment_shader: None,
+            vertex_bindings: vec![],
+            vertex_attributes: vec![],
+            topology: PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: PolygonMode::FILL,
+            cull_mode: CullModeFlags::BACK,
+            front_face: FrontFace::CLOCKWISE,
+            blend: false,
+            depth_test: false,
+            depth_write: false,
+            depth_compare_op: CompareOp::LESS,
+            set_layouts: vec![],
+            push_constant_ranges: vec![],
+            reflect: false,
+            target: None,
+        }
+    }
+}
+
+impl PipelineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vertex_shader(mut self, source: ShaderSource) -> Self {
+        self.vertex_shader = Some(source);
+        self
+    }
+
+    pub fn fragment_shader(mut self, source: ShaderSource) -> Self {
+        self.fragment_shader = Some(source);
+        self
+    }
+
+    pub fn vertex_input(
+        mut self,
+        bindings: &[VertexInputBindingDescription],
+        attributes: &[VertexInputAttributeDescription],
+    ) -> Self {
+        self.vertex_bindings = bindings.to_vec();
+        self.vertex_attributes = attributes.to_vec();
+        self
+    }
+
+    pub fn topology(mut self, topology: PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn rasterization(
+        mut self,
+        polygon_mode: PolygonMode,
+        cull_mode: CullModeFlags,
+        front_face: FrontFace,
+    ) -> Self {
+        self.polygon_mode = polygon_mode;
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    /// Enables standard alpha blending on every color attachment.
+    pub fn alpha_blending(mut self, enabled: bool) -> Self {
+        self.blend = enabled;
+        self
+    }
+
+    pub fn depth(mut self, test: bool, write: bool, compare_op: CompareOp) -> Self {
+        self.depth_test = test;
+        self.depth_write = write;
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    pub fn set_layouts(mut self, set_layouts: &[DescriptorSetLayout]) -> Self {
+        self.set_layouts = set_layouts.to_vec();
+        self
+    }
+
+    pub fn push_constant_ranges(mut self, ranges: &[PushConstantRange]) -> Self {
+        self.push_constant_ranges = ranges.to_vec();
+        self
+    }
+
+    /// Derives the descriptor set layouts, push constant ranges and, unless
+    /// given with `vertex_input`, the vertex input from the shaders. Shaders
+    /// naga can't parse are built without reflection.
+    pub fn reflect(mut self) -> Self {
+        self.reflect = true;
+        self
+    }
+
+    pub fn render_pass(mut self, render_pass: RenderPass, subpass: u32) -> Self {
+        self.target = Some(RenderTarget::RenderPass {
+            render_pass,
+            subpass,
+        });
+        self
+    }
+
+    pub fn dynamic_rendering(mut self, color_formats: &[Format], depth_format: Format) -> Self {
+        self.target = Some(RenderTarget::Dynamic {
+            color_formats: color_formats.to_vec(),
+            depth_format,
+        });
+        self
+    }
+
+    /// # Safety
+    ///
+    /// The render pass and set layouts given to the builder, and `cache` unless
+    /// it is null, must belong to `device`.
+    pub unsafe fn build(&self, device: &Device, cache: PipelineCache) -> Result<GraphicsPipeline> {
+        let vertex_source = self
+            .vertex_shader
+            .as_ref()
+            .ok_or_else(|| anyhow!("The pipeline has no vertex shader."))?;
+        let fragment_source = self
+            .fragment_shader
+            .as_ref()
+            .ok_or_else(|| anyhow!("The pipeline has no fragment shader."))?;
+        let target = self
+            .target
+            .as_ref()
+            .ok_or_else(|| anyhow!("The pipeline has no render target."))?;
+
+        let vertex_code = vertex_source.load(ShaderStage::Vertex)?;
+        let fragment_code = fragment_source.load(ShaderStage::Fragment)?;
+
+        let reflection = self.reflection(&vertex_code, &fragment_code)?;
+
+        let vert_shader_module = create_shader_module(device, &vertex_code)?;
+        let frag_shader_module = match create_shader_module(device, &fragment_code) {
+            Ok(module) => module,
+            Err(error) => {
+                device.destroy_shader_module(vert_shader_module, None);
+                return Err(error);
+            }
+        };
+
+        let result = self.create(
+            device,
+            cache,
+            target,
+            reflection,
+            vert_shader_module,
+            frag_shader_module,
+        );
+
+        device.destroy_shader_module(vert_shader_module, None);
+        device.destroy_shader_module(frag_shader_module, None);
+
+        result
+    }
+
+    /// Reflects the shaders if asked to, or returns `None` if naga can't
+    /// parse them.
+    fn reflection(
+        &self,
+        vertex_code: &[u32],
+        fragment_code: &[u32],
+    ) -> Result<Option<PipelineReflection>, ReflectError> {
+        if !self.reflect {
+            return Ok(None);
+        }
+
+        let stages = reflect_stage(vertex_code, ShaderStage::Vertex).and_then(|vertex| {
+            let fragment = reflect_stage(fragment_code, ShaderStage::Fragment)?;
+            Ok([vertex, fragment])
+        });
+
+        match stages {
+            Ok(stages) => reflect::merge(&stages).map(Some),
+            Err(error @ ReflectError::Parse { .. }) => {
+                warn!("Building the pipeline without reflection: {}", error);
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    unsafe fn create(
+        &self,
+        device: &Device,
+        cache: PipelineCache,
+        target: &RenderTarget,
+        reflection: Option<PipelineReflection>,
+        vert_shader_module: ShaderModule,
+        frag_shader_module: ShaderModule,
+    ) -> Result<GraphicsPipeline> {
+        let owned_set_layouts = match &reflection {
+            Some(reflection) => create_set_layouts(device, reflection)?,
+            None => vec![],
+        };
+
+        let (set_layouts, push_constant_ranges) = match &reflection {
+            Some(reflection) => (&owned_set_layouts, &reflection.push_constant_ranges),
+            None => (&self.set_layouts, &self.push_constant_ranges),
+        };
+
+        let reflected_bindings = reflection
+            .as_ref()
+            .and_then(|r| r.vertex_binding)
+            .into_iter()
+            .collect::<Vec<_>>();
+        let (vertex_bindings, vertex_attributes) = match &reflection {
+            Some(reflection) if self.vertex_bindings.is_empty() => {
+                (&reflected_bindings, &reflection.vertex_attributes)
+            }
+            _ => (&self.vertex_bindings, &self.vertex_attributes),
+        };
+
+        let vert_stage = PipelineShaderStageCreateInfo::builder()
+            .stage(ShaderStageFlags::VERTEX)
+            .module(vert_shader_module)
+            .name(ENTRY_POINT.to_bytes_with_nul());
+
+        let frag_stage = PipelineShaderStageCreateInfo::builder()
+            .stage(ShaderStageFlags::FRAGMENT)
+            .module(frag_shader_module)
+            .name(ENTRY_POINT.to_bytes_with_nul());
+
+        let vertex_input_state = PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(vertex_bindings)
+            .vertex_attribute_descriptions(vertex_attributes);
+
+        let input_assembly_state = PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(self.topology)
+            .primitive_restart_enable(false);
+
+        let viewport_state = PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let rasterization_state = PipelineRasterizationStateCreateInfo::builder()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(self.polygon_mode)
+            .line_width(1.0)
+            .cull_mode(self.cull_mode)
+            .front_face(self.front_face)
+            .depth_bias_enable(false);
+
+        let multisample_state = PipelineMultisampleStateCreateInfo::builder()
+            .sample_shading_enable(false)
+            .rasterization_samples(SampleCountFlags::_1);
+
+        let depth_stencil_state = PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(self.depth_test)
+            .depth_write_enable(self.depth_write)
+            .depth_compare_op(self.depth_compare_op)
+            .depth_bounds_test_enable(false)
+            .stencil_test_enable(false);
+
+        let attachment = PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(ColorComponentFlags::all())
+            .blend_enable(self.blend)
+            .src_color_blend_factor(BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(BlendOp::ADD)
+            .src_alpha_blend_factor(BlendFactor::ONE)
+            .dst_alpha_blend_factor(BlendFactor::ZERO)
+            .alpha_blend_op(BlendOp::ADD);
+
+        let color_attachment_count = match target {
+            RenderTarget::RenderPass { .. } => 1,
+            RenderTarget::Dynamic { color_formats, .. } => color_formats.len(),
+        };
+        let attachments = vec![attachment; color_attachment_count];
+        let color_blend_state = PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .attachments(&attachments)
+            .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+        let dynamic_states = &[DynamicState::VIEWPORT, DynamicState::SCISSOR];
+        let dynamic_state =
+            PipelineDynamicStateCreateInfo::builder().dynamic_states(dynamic_states);
+
+        let layout_info = PipelineLayoutCreateInfo::builder()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+
+        let layout = match device.create_pipeline_layout(&layout_info, None) {
+            Ok(layout) => layout,
+            Err(error) => {
+                owned_set_layouts
+                    .iter()
+                    .for_each(|l| device.destroy_descriptor_set_layout(*l, None));
+                return Err(error.into());
+            }
+        };
+
+        let stages = &[vert_stage, frag_stage];
+        let mut info = GraphicsPipelineCreateInfo::builder()
+            .stages(stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state)
+            .layout(layout);
+
+        let mut rendering_info;
+        match target {
+            RenderTarget::RenderPass {
+                render_pass,
+                subpass,
+            } => {
+                info = info.render_pass(*render_pass).subpass(*subpass);
+            }
+            RenderTarget::Dynamic {
+                color_formats,
+                depth_format,
+            } => {
+                rendering_info = PipelineRenderingCreateInfo::builder()
+                    .color_attachment_formats(color_formats)
+                    .depth_attachment_format(*depth_format);
+                info = info.push_next(&mut rendering_info);
+            }
+        }
+
+        match device.create_graphics_pipelines(cache, &[info], None) {
+            Ok((pipelines, _)) => {
+                info!("Created graphics pipeline.");
+                Ok(GraphicsPipeline {
+                    pipeline: pipelines[0],
+                    layout,
+                    set_layouts: owned_set_layouts,
+                    reflection,
+                })
+            }
+            Err(error) => {
+                device.destroy_pipeline_layout(layout, None);
+                owned_set_layouts
+                    .iter()
+                    .for_each(|l| device.destroy_descriptor_set_layout(*l, None));
+                Err(error.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = r#"
+@vertex
+fn vs_main(@location(0) position: vec2<f32>) -> @builtin(position) vec4<f32> {
+    return vec4(position, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4(1.0);
+}
+"#;
+
+    fn compile(stage: ShaderStage) -> Vec<u32> {
+        compile::compile(Path::new("shader.wgsl"), SHADER, Language::Wgsl, stage).unwrap()
+    }
+
+    #[test]
+    fn reflection_is_opt_in() {
+        let vertex = compile(ShaderStage::Vertex);
+        let fragment = compile(ShaderStage::Fragment);
+
+        let builder = PipelineBuilder::new();
+        assert!(builder.reflection(&vertex, &fragment).unwrap().is_none());
+
+        let reflection = builder.reflect().reflection(&vertex, &fragment).unwrap();
+        assert_eq!(reflection.unwrap().vertex_attributes.len(), 1);
+    }
+
+    #[test]
+    fn unparsable_spirv_falls_back_to_no_reflection() {
+        let vertex = compile(ShaderStage::Vertex);
+        let fragment = [SPIRV_MAGIC, 0x0001_0000, 0, 1, 0, 0xffff_ffff];
+
+        let builder = PipelineBuilder::new().reflect();
+        assert!(builder.reflection(&vertex, &fragment).unwrap().is_none());
+    }
+
+    #[test]
+    fn other_reflect_errors_are_not_ignored() {
+        let vertex = compile(ShaderStage::Vertex);
+        let fragment = compile(ShaderStage::Vertex);
+
+        let builder = PipelineBuilder::new().reflect();
+        assert!(matches!(
+            builder.reflection(&vertex, &fragment),
+            Err(ReflectError::MissingEntryPoint(ShaderStage::Fragment))
+        ));
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::Result;
+use log::{info, warn};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use vulkanalia::{
+    prelude::v1_0::*,
+    vk::{PhysicalDeviceProperties, PipelineCache, PipelineCacheCreateInfo, UUID_SIZE},
+};
+
+/// `VK_PIPELINE_CACHE_HEADER_VERSION_ONE`.
+const HEADER_VERSION_ONE: u32 = 1;
+const HEADER_SIZE: usize = 16 + UUID_SIZE;
+
+/// Why cached data does not belong to the selected device.
+#[derive(Debug, Error)]
+pub enum CacheHeaderError {
+    #[error("the header is truncated")]
+    Truncated,
+    #[error("unknown header version {0}")]
+    Version(u32),
+    #[error("it was created for vendor {found:#x}, not {expected:#x}")]
+    Vendor { found: u32, expected: u32 },
+    #[error("it was created for device {found:#x}, not {expected:#x}")]
+    Device { found: u32, expected: u32 },
+    #[error("it was created by a different driver (pipeline cache UUID mismatch)")]
+    Uuid,
+}
+
+/// `$XDG_CACHE_HOME/vk-tutorial/pipeline_cache.bin` or the platform's
+/// equivalent.
+pub fn default_path() -> Option<PathBuf> {
+    Some(
+        dirs::cache_dir()?
+            .join("vk-tutorial")
+            .join("pipeline_cache.bin"),
+    )
+}
+
+/// Checks the `VkPipelineCacheHeaderVersionOne` at the start of `data`.
+pub fn check_header(
+    data: &[u8],
+    properties: &PhysicalDeviceProperties,
+) -> Result<(), CacheHeaderError> {
+    if data.len() < HEADER_SIZE {
+        return Err(CacheHeaderError::Truncated);
+    }
+
+    let word = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+
+    let header_size = word(0) as usize;
+    if header_size < HEADER_SIZE || header_size > data.len() {
+        return Err(CacheHeaderError::Truncated);
+    }
+
+    let version = word(4);
+    if version != HEADER_VERSION_ONE {
+        return Err(CacheHeaderError::Version(version));
+    }
+
+    let vendor_id = word(8);
+    if vendor_id != properties.vendor_id {
+        return Err(CacheHeaderError::Vendor {
+            found: vendor_id,
+            expected: properties.vendor_id,
+        });
+    }
+
+    let device_id = word(12);
+    if device_id != properties.device_id {
+        return Err(CacheHeaderError::Device {
+            found: device_id,
+            expected: properties.device_id,
+        });
+    }
+
+    if data[16..HEADER_SIZE] != properties.pipeline_cache_uuid[..] {
+        return Err(CacheHeaderError::Uuid);
+    }
+
+    Ok(())
+}
+
+/// Creates a pipeline cache from the data at `path`. A missing, corrupt or
+/// mismatched file only produces an empty cache.
+///
+/// # Safety
+///
+/// `properties` must describe the physical device of `device`.
+pub unsafe fn load(
+    device: &Device,
+    properties: &PhysicalDeviceProperties,
+    path: Option<&Path>,
+) -> Result<PipelineCache> {
+    let data = path.map_or_else(Vec::new, |path| read(path, properties));
+
+    if !data.is_empty() {
+        let info = PipelineCacheCreateInfo::builder().initial_data(&data);
+        match device.create_pipeline_cache(&info, None) {
+            Ok(cache) => return Ok(cache),
+            Err(error) => warn!("Discarding the pipeline cache: {}", error),
+        }
+    }
+
+    let info = PipelineCacheCreateInfo::builder();
+    Ok(device.create_pipeline_cache(&info, None)?)
+}
+
+fn read(path: &Path, properties: &PhysicalDeviceProperties) -> Vec<u8> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(error) if error.kind() == ErrorKind::NotFound => return vec![],
+        Err(error) => {
+            warn!(
+                "Failed to read the pipeline cache `{}`: {}",
+                path.display(),
+                error
+            );
+            return vec![];
+        }
+    };
+
+    match check_header(&data, properties) {
+        Ok(()) => {
+            info!(
+                "Loaded {} bytes of pipeline cache from `{}`.",
+                data.len(),
+                path.display()
+            );
+            data
+        }
+        Err(error) => {
+            warn!(
+                "Discarding the pipeline cache `{}`: {}.",
+                path.display(),
+                error
+            );
+            vec![]
+        }
+    }
+}
+
+/// Writes the contents of `cache` to `path`, replacing the previous file
+/// only once the new one is complete.
+///
+/// # Safety
+///
+/// `cache` must belong to `device`.
+pub unsafe fn save(device: &Device, cache: PipelineCache, path: &Path) -> Result<()> {
+    let data = device.get_pipeline_cache_data(cache)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, &data)?;
+    fs::rename(&temporary, path)?;
+
+    info!(
+        "Saved {} bytes of pipeline cache to `{}`.",
+        data.len(),
+        path.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: [u8; UUID_SIZE] = [7; UUID_SIZE];
+
+    fn properties() -> PhysicalDeviceProperties {
+        PhysicalDeviceProperties {
+            vendor_id: 0x10de,
+            device_id: 0x2684,
+            pipeline_cache_uuid: UUID.into(),
+            ..Default::default()
+        }
+    }
+
+    fn header(version: u32, vendor_id: u32, device_id: u32, uuid: [u8; UUID_SIZE]) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend((HEADER_SIZE as u32).to_le_bytes());
+        data.extend(version.to_le_bytes());
+        data.extend(vendor_id.to_le_bytes());
+        data.extend(device_id.to_le_bytes());
+        data.extend(uuid);
+        // Driver data follows the header.
+        data.extend([0xab; 8]);
+        data
+    }
+
+    fn valid_header() -> Vec<u8> {
+        header(HEADER_VERSION_ONE, 0x10de, 0x2684, UUID)
+    }
+
+    #[test]
+    fn accepts_valid_header() {
+        assert!(check_header(&valid_header(), &properties()).is_ok());
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = valid_header();
+        assert!(matches!(
+            check_header(&data[..HEADER_SIZE - 1], &properties()),
+            Err(CacheHeaderError::Truncated)
+        ));
+        assert!(matches!(
+            check_header(&[], &properties()),
+            Err(CacheHeaderError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_header_size() {
+        let mut data = valid_header();
+        let too_large = data.len() as u32 + 1;
+        data[..4].copy_from_slice(&too_large.to_le_bytes());
+        assert!(matches!(
+            check_header(&data, &properties()),
+            Err(CacheHeaderError::Truncated)
+        ));
+
+        data[..4].copy_from_slice(&(HEADER_SIZE as u32 - 1).to_le_bytes());
+        assert!(matches!(
+            check_header(&data, &properties()),
+            Err(CacheHeaderError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let data = header(2, 0x10de, 0x2684, UUID);
+        assert!(matches!(
+            check_header(&data, &properties()),
+            Err(CacheHeaderError::Version(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_vendor() {
+        let data = header(HEADER_VERSION_ONE, 0x1002, 0x2684, UUID);
+        assert!(matches!(
+            check_header(&data, &properties()),
+            Err(CacheHeaderError::Vendor {
+                found: 0x1002,
+                expected: 0x10de
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_other_device() {
+        let data = header(HEADER_VERSION_ONE, 0x10de, 0x1234, UUID);
+        assert!(matches!(
+            check_header(&data, &properties()),
+            Err(CacheHeaderError::Device {
+                found: 0x1234,
+                expected: 0x2684
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_other_driver() {
+        let data = header(HEADER_VERSION_ONE, 0x10de, 0x2684, [8; UUID_SIZE]);
+        assert!(matches!(
+            check_header(&data, &properties()),
+            Err(CacheHeaderError::Uuid)
+        ));
+    }
+}
@@ -0,0 +1,746 @@
+//! Derives descriptor set layouts, push constant ranges and vertex input
+//! from SPIR-V, using naga's SPIR-V frontend.
+
+use naga::{
+    front::spv, proc::Layouter, AddressSpace, ArraySize, Binding, ImageClass, Module,
+    ResourceBinding, Scalar, ScalarKind, ShaderStage, TypeInner, VectorSize,
+};
+use std::collections::{BTreeMap, HashSet};
+use thiserror::Error;
+use vulkanalia::vk::{
+    DescriptorSetLayoutBinding, DescriptorType, Format, HasBuilder, PushConstantRange,
+    ShaderStageFlags, VertexInputAttributeDescription, VertexInputBindingDescription,
+    VertexInputRate,
+};
+
+#[derive(Debug, Error)]
+pub enum ReflectError {
+    #[error("failed to parse {stage:?} SPIR-V: {message}")]
+    Parse { stage: ShaderStage, message: String },
+    #[error("no {0:?} entry point")]
+    MissingEntryPoint(ShaderStage),
+    #[error("set {set} binding {binding} is {first:?} in one stage and {second:?} in another")]
+    DescriptorType {
+        set: u32,
+        binding: u32,
+        first: DescriptorType,
+        second: DescriptorType,
+    },
+    #[error(
+        "set {set} binding {binding} has {first} descriptors in one stage and {second} in another"
+    )]
+    DescriptorCount {
+        set: u32,
+        binding: u32,
+        first: u32,
+        second: u32,
+    },
+    #[error("fragment input location {0} is not written by the vertex stage")]
+    MissingVertexOutput(u32),
+    #[error(
+        "location {location} is {output:?} in the vertex stage and {input:?} in the fragment stage"
+    )]
+    InterfaceType {
+        location: u32,
+        output: Format,
+        input: Format,
+    },
+    #[error("vertex input location {0} has an unsupported type")]
+    UnsupportedVertexInput(u32),
+}
+
+/// A descriptor used by a shader stage.
+#[derive(Copy, Clone, Debug)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: DescriptorType,
+    pub count: u32,
+}
+
+/// What a single shader stage declares.
+#[derive(Clone, Debug)]
+pub struct StageReflection {
+    pub stage: ShaderStage,
+    pub bindings: Vec<ReflectedBinding>,
+    /// `(offset, size)` of the push constant block.
+    pub push_constants: Option<(u32, u32)>,
+    /// Input locations and their formats.
+    pub inputs: BTreeMap<u32, Format>,
+    /// Output locations and their formats.
+    pub outputs: BTreeMap<u32, Format>,
+}
+
+/// The layouts of a pipeline, merged across its stages.
+#[derive(Clone, Debug, Default)]
+pub struct PipelineReflection {
+    /// Bindings of every descriptor set, by set index.
+    pub sets: BTreeMap<u32, Vec<DescriptorSetLayoutBinding>>,
+    pub push_constant_ranges: Vec<PushConstantRange>,
+    /// The vertex inputs as a single interleaved binding, if there are any.
+    pub vertex_binding: Option<VertexInputBindingDescription>,
+    pub vertex_attributes: Vec<VertexInputAttributeDescription>,
+}
+
+pub fn reflect_stage(code: &[u32], stage: ShaderStage) -> Result<StageReflection, ReflectError> {
+    let options = spv::Options {
+        adjust_coordinate_space: false,
+        strict_capabilities: false,
+        block_ctx_dump_prefix: None,
+    };
+    let bytes = code
+        .iter()
+        .flat_map(|w| w.to_le_bytes())
+        .collect::<Vec<_>>();
+    let module = spv::parse_u8_slice(&bytes, &options).map_err(|e| ReflectError::Parse {
+        stage,
+        message: e.to_string(),
+    })?;
+
+    let mut layouter = Layouter::default();
+    layouter
+        .update(module.to_ctx())
+        .map_err(|e| ReflectError::Parse {
+            stage,
+            message: e.to_string(),
+        })?;
+
+    let combined = combined_image_samplers(code);
+    let mut bindings = vec![];
+    let mut push_constants = None;
+
+    for (_, global) in module.global_variables.iter() {
+        let inner = &module.types[global.ty].inner;
+
+        if global.space == AddressSpace::PushConstant {
+            push_constants = Some(match inner {
+                TypeInner::Struct { members, span } => {
+                    let offset = members.iter().map(|m| m.offset).min().unwrap_or(0);
+                    (offset, span - offset)
+                }
+                _ => (0, layouter[global.ty].size),
+            });
+            continue;
+        }
+
+        let Some(ResourceBinding { group, binding }) = global.binding else {
+            continue;
+        };
+
+        let (inner, count) = match inner {
+            TypeInner::BindingArray { base, size } => (
+                &module.types[*base].inner,
+                match size {
+                    ArraySize::Constant(size) => size.get(),
+                    _ => 1,
+                },
+            ),
+            inner => (inner, 1),
+        };
+
+        let descriptor_type = match (global.space, inner) {
+            (AddressSpace::Uniform, _) => DescriptorType::UNIFORM_BUFFER,
+            (AddressSpace::Storage { .. }, _) => DescriptorType::STORAGE_BUFFER,
+            (_, TypeInner::Sampler { .. }) => DescriptorType::SAMPLER,
+            (_, TypeInner::Image { .. }) if combined.contains(&(group, binding)) => {
+                DescriptorType::COMBINED_IMAGE_SAMPLER
+            }
+            (
+                _,
+                TypeInner::Image {
+                    class: ImageClass::Storage { .. },
+                    ..
+                },
+            ) => DescriptorType::STORAGE_IMAGE,
+            (_, TypeInner::Image { .. }) => DescriptorType::SAMPLED_IMAGE,
+            (_, TypeInner::AccelerationStructure { .. }) => {
+                DescriptorType::ACCELERATION_STRUCTURE_KHR
+            }
+            _ => continue,
+        };
+
+        bindings.push(ReflectedBinding {
+            set: group,
+            binding,
+            descriptor_type,
+            count,
+        });
+    }
+
+    let entry_point = module
+        .entry_points
+        .iter()
+        .find(|e| e.stage == stage)
+        .ok_or(ReflectError::MissingEntryPoint(stage))?;
+
+    let mut inputs = BTreeMap::new();
+    for argument in &entry_point.function.arguments {
+        collect_locations(&module, argument.ty, argument.binding.as_ref(), &mut inputs);
+    }
+
+    let mut outputs = BTreeMap::new();
+    if let Some(result) = &entry_point.function.result {
+        collect_locations(&module, result.ty, result.binding.as_ref(), &mut outputs);
+    }
+
+    Ok(StageReflection {
+        stage,
+        bindings,
+        push_constants,
+        inputs,
+        outputs,
+    })
+}
+
+/// Merges the stages of a pipeline. A descriptor used by several stages
+/// must have the same type and count in each of them.
+pub fn merge(stages: &[StageReflection]) -> Result<PipelineReflection, ReflectError> {
+    let mut sets = BTreeMap::<u32, BTreeMap<u32, DescriptorSetLayoutBinding>>::new();
+    let mut push_constant_ranges = vec![];
+
+    for stage in stages {
+        let stage_flags = stage_flags(stage.stage);
+
+        for b in &stage.bindings {
+            let bindings = sets.entry(b.set).or_default();
+            match bindings.get_mut(&b.binding) {
+                Some(existing) if existing.descriptor_type != b.descriptor_type => {
+                    return Err(ReflectError::DescriptorType {
+                        set: b.set,
+                        binding: b.binding,
+                        first: existing.descriptor_type,
+                        second: b.descriptor_type,
+                    });
+                }
+                Some(existing) if existing.descriptor_count != b.count => {
+                    return Err(ReflectError::DescriptorCount {
+                        set: b.set,
+                        binding: b.binding,
+                        first: existing.descriptor_count,
+                        second: b.count,
+                    });
+                }
+                Some(existing) => existing.stage_flags |= stage_flags,
+                None => {
+                    bindings.insert(
+                        b.binding,
+                        DescriptorSetLayoutBinding::builder()
+                            .binding(b.binding)
+                            .descriptor_type(b.descriptor_type)
+                            .descriptor_count(b.count)
+                            .stage_flags(stage_flags)
+                            .build(),
+                    );
+                }
+            }
+        }
+
+        if let Some((offset, size)) = stage.push_constants {
+            push_constant_ranges.push(
+                PushConstantRange::builder()
+                    .stage_flags(stage_flags)
+                    .offset(offset)
+                    .size(size)
+                    .build(),
+            );
+        }
+    }
+
+    let vertex = stages.iter().find(|s| s.stage == ShaderStage::Vertex);
+    let fragment = stages.iter().find(|s| s.stage == ShaderStage::Fragment);
+
+    if let (Some(vertex), Some(fragment)) = (vertex, fragment) {
+        for (location, input) in &fragment.inputs {
+            match vertex.outputs.get(location) {
+                None => return Err(ReflectError::MissingVertexOutput(*location)),
+                Some(output) if output != input => {
+                    return Err(ReflectError::InterfaceType {
+                        location: *location,
+                        output: *output,
+                        input: *input,
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    let mut vertex_binding = None;
+    let mut vertex_attributes = vec![];
+    if let Some(vertex) = vertex.filter(|v| !v.inputs.is_empty()) {
+        let mut offset = 0;
+        for (location, format) in &vertex.inputs {
+            let size =
+                format_size(*format).ok_or(ReflectError::UnsupportedVertexInput(*location))?;
+            vertex_attributes.push(
+                VertexInputAttributeDescription::builder()
+                    .binding(0)
+                    .location(*location)
+                    .format(*format)
+                    .offset(offset)
+                    .build(),
+            );
+            offset += size;
+        }
+
+        vertex_binding = Some(
+            VertexInputBindingDescription::builder()
+                .binding(0)
+                .stride(offset)
+                .input_rate(VertexInputRate::VERTEX)
+                .build(),
+        );
+    }
+
+    Ok(PipelineReflection {
+        sets: sets
+            .into_iter()
+            .map(|(set, bindings)| (set, bindings.into_values().collect()))
+            .collect(),
+        push_constant_ranges,
+        vertex_binding,
+        vertex_attributes,
+    })
+}
+
+pub fn stage_flags(stage: ShaderStage) -> ShaderStageFlags {
+    match stage {
+        ShaderStage::Vertex => ShaderStageFlags::VERTEX,
+        ShaderStage::Fragment => ShaderStageFlags::FRAGMENT,
+        ShaderStage::Compute => ShaderStageFlags::COMPUTE,
+        _ => ShaderStageFlags::ALL,
+    }
+}
+
+/// Adds the user-defined locations of an entry point argument or result,
+/// looking into structs.
+fn collect_locations(
+    module: &Module,
+    ty: naga::Handle<naga::Type>,
+    binding: Option<&Binding>,
+    locations: &mut BTreeMap<u32, Format>,
+) {
+    match (binding, &module.types[ty].inner) {
+        (Some(Binding::Location { location, .. }), inner) => {
+            locations.insert(*location, format(inner).unwrap_or(Format::UNDEFINED));
+        }
+        (None, TypeInner::Struct { members, .. }) => {
+            for member in members {
+                collect_locations(module, member.ty, member.binding.as_ref(), locations);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn format(inner: &TypeInner) -> Option<Format> {
+    let (scalar, size) = match *inner {
+        TypeInner::Scalar(scalar) => (scalar, 1),
+        TypeInner::Vector { size, scalar } => (
+            scalar,
+            match size {
+                VectorSize::Bi => 2,
+                VectorSize::Tri => 3,
+                VectorSize::Quad => 4,
+            },
+        ),
+        _ => return None,
+    };
+
+    let formats = match scalar {
+        Scalar {
+            kind: ScalarKind::Float,
+            width: 4,
+        } => [
+            Format::R32_SFLOAT,
+            Format::R32G32_SFLOAT,
+            Format::R32G32B32_SFLOAT,
+            Format::R32G32B32A32_SFLOAT,
+        ],
+        Scalar {
+            kind: ScalarKind::Sint,
+            width: 4,
+        } => [
+            Format::R32_SINT,
+            Format::R32G32_SINT,
+            Format::R32G32B32_SINT,
+            Format::R32G32B32A32_SINT,
+        ],
+        Scalar {
+            kind: ScalarKind::Uint,
+            width: 4,
+        } => [
+            Format::R32_UINT,
+            Format::R32G32_UINT,
+            Format::R32G32B32_UINT,
+            Format::R32G32B32A32_UINT,
+        ],
+        _ => return None,
+    };
+
+    Some(formats[size - 1])
+}
+
+fn format_size(format: Format) -> Option<u32> {
+    match format {
+        Format::R32_SFLOAT | Format::R32_SINT | Format::R32_UINT => Some(4),
+        Format::R32G32_SFLOAT | Format::R32G32_SINT | Format::R32G32_UINT => Some(8),
+        Format::R32G32B32_SFLOAT | Format::R32G32B32_SINT | Format::R32G32B32_UINT => Some(12),
+        Format::R32G32B32A32_SFLOAT | Format::R32G32B32A32_SINT | Format::R32G32B32A32_UINT => {
+            Some(16)
+        }
+        _ => None,
+    }
+}
+
+/// The `(set, binding)` of every combined image sampler variable.
+///
+/// naga reads these as plain images, so they are found in the raw SPIR-V.
+fn combined_image_samplers(code: &[u32]) -> HashSet<(u32, u32)> {
+    const OP_DECORATE: u32 = 71;
+    const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+    const OP_TYPE_ARRAY: u32 = 28;
+    const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+    const OP_TYPE_POINTER: u32 = 32;
+    const OP_VARIABLE: u32 = 59;
+    const DECORATION_BINDING: u32 = 33;
+    const DECORATION_DESCRIPTOR_SET: u32 = 34;
+
+    let mut sampled_images = HashSet::new();
+    let mut element_types = BTreeMap::new();
+    let mut pointers = BTreeMap::new();
+    let mut variables = vec![];
+    let mut sets = BTreeMap::new();
+    let mut bindings = BTreeMap::new();
+
+    // Skip the 5 word header.
+    let mut words = code.get(5..).unwrap_or_default();
+    while let Some(&first) = words.first() {
+        let count = (first >> 16) as usize;
+        if count == 0 || count > words.len() {
+            break;
+        }
+
+        let operands = &words[1..count];
+        match (first & 0xffff, operands) {
+            (OP_TYPE_SAMPLED_IMAGE, [id, ..]) => {
+                sampled_images.insert(*id);
+            }
+            (OP_TYPE_ARRAY | OP_TYPE_RUNTIME_ARRAY, [id, element, ..]) => {
+                element_types.insert(*id, *element);
+            }
+            (OP_TYPE_POINTER, [id, _, pointee]) => {
+                pointers.insert(*id, *pointee);
+            }
+            (OP_VARIABLE, [ty, id, ..]) => variables.push((*ty, *id)),
+            (OP_DECORATE, [id, DECORATION_DESCRIPTOR_SET, set]) => {
+                sets.insert(*id, *set);
+            }
+            (OP_DECORATE, [id, DECORATION_BINDING, binding]) => {
+                bindings.insert(*id, *binding);
+            }
+            _ => {}
+        }
+
+        words = &words[count..];
+    }
+
+    variables
+        .into_iter()
+        .filter(|(ty, _)| {
+            let Some(mut ty) = pointers.get(ty).copied() else {
+                return false;
+            };
+            while let Some(element) = element_types.get(&ty) {
+                ty = *element;
+            }
+            sampled_images.contains(&ty)
+        })
+        .filter_map(|(_, id)| Some((*sets.get(&id)?, *bindings.get(&id)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::{compile, Language};
+    use std::path::Path;
+
+    fn stage(stage: ShaderStage) -> StageReflection {
+        StageReflection {
+            stage,
+            bindings: vec![],
+            push_constants: None,
+            inputs: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+        }
+    }
+
+    fn binding(binding: u32, descriptor_type: DescriptorType, count: u32) -> ReflectedBinding {
+        ReflectedBinding {
+            set: 0,
+            binding,
+            descriptor_type,
+            count,
+        }
+    }
+
+    #[test]
+    fn merge_descriptor_type_mismatch() {
+        let mut vertex = stage(ShaderStage::Vertex);
+        vertex.bindings = vec![binding(1, DescriptorType::UNIFORM_BUFFER, 1)];
+        let mut fragment = stage(ShaderStage::Fragment);
+        fragment.bindings = vec![binding(1, DescriptorType::STORAGE_BUFFER, 1)];
+
+        assert!(matches!(
+            merge(&[vertex, fragment]),
+            Err(ReflectError::DescriptorType {
+                set: 0,
+                binding: 1,
+                first: DescriptorType::UNIFORM_BUFFER,
+                second: DescriptorType::STORAGE_BUFFER,
+            })
+        ));
+    }
+
+    #[test]
+    fn merge_descriptor_count_mismatch() {
+        let mut vertex = stage(ShaderStage::Vertex);
+        vertex.bindings = vec![binding(0, DescriptorType::SAMPLED_IMAGE, 4)];
+        let mut fragment = stage(ShaderStage::Fragment);
+        fragment.bindings = vec![binding(0, DescriptorType::SAMPLED_IMAGE, 2)];
+
+        assert!(matches!(
+            merge(&[vertex, fragment]),
+            Err(ReflectError::DescriptorCount {
+                set: 0,
+                binding: 0,
+                first: 4,
+                second: 2,
+            })
+        ));
+    }
+
+    #[test]
+    fn merge_shared_binding_stage_flags() {
+        let mut vertex = stage(ShaderStage::Vertex);
+        vertex.bindings = vec![binding(0, DescriptorType::UNIFORM_BUFFER, 1)];
+        let mut fragment = stage(ShaderStage::Fragment);
+        fragment.bindings = vec![binding(0, DescriptorType::UNIFORM_BUFFER, 1)];
+
+        let reflection = merge(&[vertex, fragment]).unwrap();
+        assert_eq!(reflection.sets[&0].len(), 1);
+        assert_eq!(
+            reflection.sets[&0][0].stage_flags,
+            ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT
+        );
+    }
+
+    #[test]
+    fn merge_missing_vertex_output() {
+        let mut vertex = stage(ShaderStage::Vertex);
+        vertex.outputs = BTreeMap::from([(0, Format::R32G32_SFLOAT)]);
+        let mut fragment = stage(ShaderStage::Fragment);
+        fragment.inputs = BTreeMap::from([(0, Format::R32G32_SFLOAT), (1, Format::R32_SFLOAT)]);
+
+        assert!(matches!(
+            merge(&[vertex, fragment]),
+            Err(ReflectError::MissingVertexOutput(1))
+        ));
+    }
+
+    #[test]
+    fn merge_interface_type_mismatch() {
+        let mut vertex = stage(ShaderStage::Vertex);
+        vertex.outputs = BTreeMap::from([(2, Format::R32G32B32_SFLOAT)]);
+        let mut fragment = stage(ShaderStage::Fragment);
+        fragment.inputs = BTreeMap::from([(2, Format::R32G32B32A32_SFLOAT)]);
+
+        assert!(matches!(
+            merge(&[vertex, fragment]),
+            Err(ReflectError::InterfaceType {
+                location: 2,
+                output: Format::R32G32B32_SFLOAT,
+                input: Format::R32G32B32A32_SFLOAT,
+            })
+        ));
+    }
+
+    #[test]
+    fn vertex_attributes_are_packed_in_location_order() {
+        let mut vertex = stage(ShaderStage::Vertex);
+        vertex.inputs = BTreeMap::from([
+            (2, Format::R32G32B32A32_SFLOAT),
+            (0, Format::R32G32B32_SFLOAT),
+            (1, Format::R32_UINT),
+        ]);
+
+        let reflection = merge(&[vertex]).unwrap();
+        let attributes = reflection
+            .vertex_attributes
+            .iter()
+            .map(|a| (a.location, a.format, a.offset))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            attributes,
+            vec![
+                (0, Format::R32G32B32_SFLOAT, 0),
+                (1, Format::R32_UINT, 12),
+                (2, Format::R32G32B32A32_SFLOAT, 16),
+            ]
+        );
+        assert_eq!(reflection.vertex_binding.unwrap().stride, 32);
+    }
+
+    #[test]
+    fn unsupported_vertex_input() {
+        let mut vertex = stage(ShaderStage::Vertex);
+        vertex.inputs = BTreeMap::from([(0, Format::R32_SFLOAT), (3, Format::UNDEFINED)]);
+
+        assert!(matches!(
+            merge(&[vertex]),
+            Err(ReflectError::UnsupportedVertexInput(3))
+        ));
+    }
+
+    #[test]
+    fn no_vertex_inputs() {
+        let reflection = merge(&[stage(ShaderStage::Vertex)]).unwrap();
+        assert!(reflection.vertex_binding.is_none());
+        assert!(reflection.vertex_attributes.is_empty());
+    }
+
+    /// Encodes a SPIR-V instruction.
+    fn op(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    #[test]
+    fn combined_image_samplers_in_raw_spirv() {
+        let code = [
+            vec![0x0723_0203, 0x0001_0000, 0, 20, 0],
+            // %10: set 1 binding 2, %11: set 0 binding 3, %12: set 0 binding 4.
+            op(71, &[10, 34, 1]),
+            op(71, &[10, 33, 2]),
+            op(71, &[11, 34, 0]),
+            op(71, &[11, 33, 3]),
+            op(71, &[12, 34, 0]),
+            op(71, &[12, 33, 4]),
+            // %3 = OpTypeSampledImage %2, %4 = OpTypeArray %3 %9.
+            op(27, &[3, 2]),
+            op(28, &[4, 3, 9]),
+            // Pointers to the sampled image, the array and the plain image %2.
+            op(32, &[5, 0, 3]),
+            op(32, &[6, 0, 4]),
+            op(32, &[7, 0, 2]),
+            op(59, &[5, 10, 0]),
+            op(59, &[6, 11, 0]),
+            op(59, &[7, 12, 0]),
+        ]
+        .concat();
+
+        assert_eq!(
+            combined_image_samplers(&code),
+            HashSet::from([(1, 2), (0, 3)])
+        );
+
+        // A truncated instruction ends the scan instead of reading past the end.
+        let truncated = [&code[..code.len() - 4], &[(8 << 16) | 59]].concat();
+        assert_eq!(
+            combined_image_samplers(&truncated),
+            HashSet::from([(1, 2), (0, 3)])
+        );
+        assert!(combined_image_samplers(&code[..3]).is_empty());
+    }
+
+    const TEXTURED: &str = r#"
+struct Globals {
+    transform: mat4x4<f32>,
+}
+
+struct Push {
+    tint: vec4<f32>,
+    scale: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@group(0) @binding(0) var<uniform> globals: Globals;
+@group(0) @binding(1) var color_texture: texture_2d<f32>;
+@group(0) @binding(2) var color_sampler: sampler;
+var<push_constant> push: Push;
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, @location(1) uv: vec2<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = globals.transform * vec4(position * push.scale, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    return textureSample(color_texture, color_sampler, uv) * push.tint;
+}
+"#;
+
+    #[test]
+    fn reflect_compiled_wgsl() {
+        let stages = [ShaderStage::Vertex, ShaderStage::Fragment]
+            .into_iter()
+            .map(|stage| {
+                let code =
+                    compile(Path::new("textured.wgsl"), TEXTURED, Language::Wgsl, stage).unwrap();
+                reflect_stage(&code, stage).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let reflection = merge(&stages).unwrap();
+
+        let bindings = reflection.sets[&0]
+            .iter()
+            .map(|b| (b.binding, b.descriptor_type, b.stage_flags))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bindings,
+            vec![
+                (0, DescriptorType::UNIFORM_BUFFER, ShaderStageFlags::VERTEX),
+                (1, DescriptorType::SAMPLED_IMAGE, ShaderStageFlags::FRAGMENT),
+                (2, DescriptorType::SAMPLER, ShaderStageFlags::FRAGMENT),
+            ]
+        );
+        assert_eq!(reflection.sets.len(), 1);
+
+        let push_constants = reflection
+            .push_constant_ranges
+            .iter()
+            .map(|r| (r.stage_flags, r.offset, r.size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            push_constants,
+            vec![
+                (ShaderStageFlags::VERTEX, 0, 32),
+                (ShaderStageFlags::FRAGMENT, 0, 32),
+            ]
+        );
+
+        let attributes = reflection
+            .vertex_attributes
+            .iter()
+            .map(|a| (a.location, a.format, a.offset))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            attributes,
+            vec![
+                (0, Format::R32G32B32_SFLOAT, 0),
+                (1, Format::R32G32_SFLOAT, 12),
+            ]
+        );
+        assert_eq!(reflection.vertex_binding.unwrap().stride, 20);
+    }
+}
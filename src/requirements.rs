@@ -0,0 +1,166 @@
+use anyhow::Result;
+use log::info;
+use std::{
+    collections::{BTreeSet, HashSet},
+    os::raw::c_char,
+};
+use vulkanalia::{prelude::v1_0::*, vk::ExtensionName};
+
+use crate::error::AppError;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Requirement {
+    /// Instance creation fails if the item is not available.
+    Required,
+    /// The item is enabled only if it is available.
+    Optional,
+}
+
+/// The layers and instance extensions an instance is created with.
+#[derive(Clone, Debug, Default)]
+pub struct InstanceRequirements {
+    layers: Vec<(ExtensionName, Requirement)>,
+    extensions: Vec<(ExtensionName, Requirement)>,
+}
+
+impl InstanceRequirements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn layer(mut self, name: ExtensionName, requirement: Requirement) -> Self {
+        add(&mut self.layers, name, requirement);
+        self
+    }
+
+    pub fn extension(mut self, name: ExtensionName, requirement: Requirement) -> Self {
+        add(&mut self.extensions, name, requirement);
+        self
+    }
+
+    pub fn require_layer(self, name: ExtensionName) -> Self {
+        self.layer(name, Requirement::Required)
+    }
+
+    pub fn optional_layer(self, name: ExtensionName) -> Self {
+        self.layer(name, Requirement::Optional)
+    }
+
+    pub fn require_extension(self, name: ExtensionName) -> Self {
+        self.extension(name, Requirement::Required)
+    }
+
+    pub fn optional_extension(self, name: ExtensionName) -> Self {
+        self.extension(name, Requirement::Optional)
+    }
+
+    /// Checks the requirements against what the loader provides. Extensions
+    /// may be provided by the loader itself or by any enabled layer.
+    ///
+    /// # Safety
+    ///
+    /// `entry` must have been loaded from a genuine Vulkan loader.
+    pub unsafe fn negotiate(&self, entry: &Entry) -> Result<NegotiatedInstance> {
+        let available_layers = entry
+            .enumerate_instance_layer_properties()?
+            .iter()
+            .map(|l| l.layer_name)
+            .collect::<HashSet<_>>();
+
+        let (layers, missing_layers) = resolve("layer", &self.layers, &available_layers);
+
+        let mut available_extensions = entry
+            .enumerate_instance_extension_properties(None)?
+            .iter()
+            .map(|e| e.extension_name)
+            .collect::<HashSet<_>>();
+
+        for layer in &layers {
+            available_extensions.extend(
+                entry
+                    .enumerate_instance_extension_properties(Some(layer.as_bytes()))?
+                    .iter()
+                    .map(|e| e.extension_name),
+            );
+        }
+
+        let (extensions, missing_extensions) =
+            resolve("extension", &self.extensions, &available_extensions);
+
+        // A missing layer usually explains missing extensions as well, so it
+        // is reported first.
+        if !missing_layers.is_empty() {
+            return Err(AppError::MissingLayer(names(&missing_layers)).into());
+        }
+
+        if !missing_extensions.is_empty() {
+            return Err(AppError::MissingExtension(names(&missing_extensions)).into());
+        }
+
+        Ok(NegotiatedInstance { layers, extensions })
+    }
+}
+
+/// The layers and instance extensions that were actually enabled.
+#[derive(Clone, Debug, Default)]
+pub struct NegotiatedInstance {
+    pub layers: BTreeSet<ExtensionName>,
+    pub extensions: BTreeSet<ExtensionName>,
+}
+
+impl NegotiatedInstance {
+    pub fn has_layer(&self, name: &ExtensionName) -> bool {
+        self.layers.contains(name)
+    }
+
+    pub fn has_extension(&self, name: &ExtensionName) -> bool {
+        self.extensions.contains(name)
+    }
+
+    pub fn layer_names(&self) -> Vec<*const c_char> {
+        self.layers.iter().map(|l| l.as_ptr()).collect()
+    }
+
+    pub fn extension_names(&self) -> Vec<*const c_char> {
+        self.extensions.iter().map(|e| e.as_ptr()).collect()
+    }
+}
+
+/// Adds `name`, upgrading an optional entry if it is later required.
+fn add(
+    items: &mut Vec<(ExtensionName, Requirement)>,
+    name: ExtensionName,
+    requirement: Requirement,
+) {
+    match items.iter_mut().find(|(n, _)| *n == name) {
+        Some((_, r)) if requirement == Requirement::Required => *r = requirement,
+        Some(_) => {}
+        None => items.push((name, requirement)),
+    }
+}
+
+/// Splits `items` into the available ones and the missing required ones.
+fn resolve(
+    kind: &str,
+    items: &[(ExtensionName, Requirement)],
+    available: &HashSet<ExtensionName>,
+) -> (BTreeSet<ExtensionName>, Vec<ExtensionName>) {
+    let mut enabled = BTreeSet::new();
+    let mut missing = vec![];
+
+    for (name, requirement) in items {
+        if available.contains(name) {
+            enabled.insert(*name);
+        } else if *requirement == Requirement::Required {
+            missing.push(*name);
+        } else {
+            info!("Optional instance {} `{}` is not available.", kind, name);
+        }
+    }
+
+    (enabled, missing)
+}
+
+fn names(names: &[ExtensionName]) -> Vec<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
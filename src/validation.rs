@@ -0,0 +1,265 @@
+use log::{log, warn, Level};
+use std::{
+    collections::VecDeque,
+    ffi::CStr,
+    fmt,
+    os::raw::{c_char, c_void},
+    panic::{catch_unwind, AssertUnwindSafe},
+    slice,
+    sync::Mutex,
+};
+use vulkanalia::vk::{
+    Bool32, DebugUtilsLabelEXT, DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT,
+    DebugUtilsMessengerCallbackDataEXT, ObjectType, FALSE,
+};
+
+/// The `log` target validation messages are reported under.
+pub const LOG_TARGET: &str = "vk::validation";
+
+/// The most messages kept before the oldest ones are dropped.
+const MAX_MESSAGES: usize = 4096;
+
+#[derive(Clone, Debug)]
+pub struct ValidationObject {
+    pub object_type: ObjectType,
+    pub handle: u64,
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ValidationMessage {
+    pub severity: DebugUtilsMessageSeverityFlagsEXT,
+    pub types: DebugUtilsMessageTypeFlagsEXT,
+    pub message_id_name: Option<String>,
+    pub message_id_number: i32,
+    pub message: String,
+    pub objects: Vec<ValidationObject>,
+    pub queue_labels: Vec<String>,
+    pub cmd_buf_labels: Vec<String>,
+}
+
+impl ValidationMessage {
+    pub fn is_error(&self) -> bool {
+        self.severity >= DebugUtilsMessageSeverityFlagsEXT::ERROR
+    }
+
+    pub fn level(&self) -> Level {
+        if self.severity >= DebugUtilsMessageSeverityFlagsEXT::ERROR {
+            Level::Error
+        } else if self.severity >= DebugUtilsMessageSeverityFlagsEXT::WARNING {
+            Level::Warn
+        } else if self.severity >= DebugUtilsMessageSeverityFlagsEXT::INFO {
+            Level::Info
+        } else {
+            Level::Trace
+        }
+    }
+
+    /// Whether `id` names this message, either by its message ID name or by
+    /// its message ID number in decimal or `0x` hexadecimal.
+    pub fn matches_id(&self, id: &str) -> bool {
+        if self.message_id_name.as_deref() == Some(id) {
+            return true;
+        }
+
+        let number = match id.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok().map(|n| n as i32),
+            None => id.parse::<i32>().ok(),
+        };
+
+        number == Some(self.message_id_number)
+    }
+
+    unsafe fn from_raw(
+        severity: DebugUtilsMessageSeverityFlagsEXT,
+        types: DebugUtilsMessageTypeFlagsEXT,
+        data: &DebugUtilsMessengerCallbackDataEXT,
+    ) -> Self {
+        let labels = |labels, count| {
+            raw_slice::<DebugUtilsLabelEXT>(labels, count)
+                .iter()
+                .filter_map(|l| string(l.label_name))
+                .collect()
+        };
+
+        let objects = raw_slice(data.objects, data.object_count)
+            .iter()
+            .map(|o| ValidationObject {
+                object_type: o.object_type,
+                handle: o.object_handle,
+                name: string(o.object_name),
+            })
+            .collect();
+
+        Self {
+            severity,
+            types,
+            message_id_name: string(data.message_id_name),
+            message_id_number: data.message_id_number,
+            message: string(data.message).unwrap_or_default(),
+            objects,
+            queue_labels: labels(data.queue_labels, data.queue_label_count),
+            cmd_buf_labels: labels(data.cmd_buf_labels, data.cmd_buf_label_count),
+        }
+    }
+}
+
+impl fmt::Display for ValidationMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({:?}) ", self.types)?;
+
+        if let Some(name) = &self.message_id_name {
+            write!(f, "[{} {:#x}] ", name, self.message_id_number)?;
+        }
+
+        write!(f, "{}", self.message)?;
+
+        for object in &self.objects {
+            write!(
+                f,
+                "\n    object {:?} {:#x}",
+                object.object_type, object.handle
+            )?;
+            if let Some(name) = &object.name {
+                write!(f, " `{}`", name)?;
+            }
+        }
+
+        if !self.queue_labels.is_empty() {
+            write!(f, "\n    queue labels: {}", self.queue_labels.join(" > "))?;
+        }
+
+        if !self.cmd_buf_labels.is_empty() {
+            write!(
+                f,
+                "\n    command buffer labels: {}",
+                self.cmd_buf_labels.join(" > ")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct SinkState {
+    messages: VecDeque<ValidationMessage>,
+    dropped: usize,
+    /// The first error reported since the last call to `take_error`.
+    pending_error: Option<String>,
+}
+
+/// Collects messages from the debug messenger. A pointer to the sink is
+/// passed to the callback through `p_user_data`, so it must outlive the
+/// messenger.
+#[derive(Debug, Default)]
+pub struct ValidationSink {
+    state: Mutex<SinkState>,
+    /// Message IDs that are neither logged nor collected.
+    muted_ids: Vec<String>,
+}
+
+impl ValidationSink {
+    pub fn new(muted_ids: Vec<String>) -> Self {
+        Self {
+            state: Mutex::default(),
+            muted_ids,
+        }
+    }
+
+    fn is_muted(&self, message: &ValidationMessage) -> bool {
+        self.muted_ids.iter().any(|id| message.matches_id(id))
+    }
+
+    fn push(&self, message: ValidationMessage) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        if message.is_error() && state.pending_error.is_none() {
+            state.pending_error = Some(message.message.clone());
+        }
+
+        if state.messages.len() == MAX_MESSAGES {
+            state.messages.pop_front();
+            state.dropped += 1;
+        }
+
+        state.messages.push_back(message);
+    }
+
+    /// Removes and returns every message collected so far.
+    pub fn take(&self) -> Vec<ValidationMessage> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        if state.dropped > 0 {
+            warn!("Dropped {} validation messages.", state.dropped);
+            state.dropped = 0;
+        }
+
+        state.messages.drain(..).collect()
+    }
+
+    /// Returns the first error reported since the last call, if any.
+    pub fn take_error(&self) -> Option<String> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.pending_error.take()
+    }
+}
+
+unsafe fn string(pointer: *const c_char) -> Option<String> {
+    if pointer.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(pointer).to_string_lossy().into_owned())
+    }
+}
+
+unsafe fn raw_slice<'a, T>(pointer: *const T, count: u32) -> &'a [T] {
+    if pointer.is_null() || count == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(pointer, count as usize)
+    }
+}
+
+/// The debug messenger callback. It must never unwind, since that would
+/// abort the process across the FFI boundary, so panics (e.g. in a logger
+/// backend) are caught and the message is dropped.
+pub(crate) extern "system" fn debug_callback(
+    severity: DebugUtilsMessageSeverityFlagsEXT,
+    type_: DebugUtilsMessageTypeFlagsEXT,
+    data: *const DebugUtilsMessengerCallbackDataEXT,
+    user_data: *mut c_void,
+) -> Bool32 {
+    // The sink recovers from a poisoned lock, so it stays usable after a
+    // caught panic.
+    catch_unwind(AssertUnwindSafe(|| {
+        handle_message(severity, type_, data, user_data)
+    }))
+    .unwrap_or(FALSE)
+}
+
+fn handle_message(
+    severity: DebugUtilsMessageSeverityFlagsEXT,
+    type_: DebugUtilsMessageTypeFlagsEXT,
+    data: *const DebugUtilsMessengerCallbackDataEXT,
+    user_data: *mut c_void,
+) -> Bool32 {
+    if data.is_null() {
+        return FALSE;
+    }
+
+    let message = unsafe { ValidationMessage::from_raw(severity, type_, &*data) };
+    let sink = unsafe { (user_data as *const ValidationSink).as_ref() };
+
+    if sink.is_some_and(|s| s.is_muted(&message)) {
+        return FALSE;
+    }
+
+    log!(target: LOG_TARGET, message.level(), "{}", message);
+
+    if let Some(sink) = sink {
+        sink.push(message);
+    }
+
+    FALSE
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use log::{debug, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver},
+        Mutex,
+    },
+};
+
+/// Watches shader files for changes.
+///
+/// The parent directories are watched rather than the files themselves, so
+/// editors that save by replacing the file are picked up as well.
+#[derive(Debug)]
+pub struct ShaderWatcher {
+    // Never read, but events stop once it is dropped.
+    #[allow(dead_code)]
+    watcher: RecommendedWatcher,
+    changes: Mutex<Receiver<PathBuf>>,
+}
+
+impl ShaderWatcher {
+    pub fn new<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Result<Self> {
+        let files = paths
+            .into_iter()
+            .map(absolute_path)
+            .collect::<Result<HashSet<_>>>()?;
+
+        let (sender, changes) = channel();
+        let watched = files.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let event = match event {
+                Ok(event) => event,
+                Err(error) => return warn!("Shader watcher error: {}", error),
+            };
+
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                return;
+            }
+
+            for path in event.paths.into_iter().filter(|p| watched.contains(p)) {
+                // The receiver is gone once the app is destroyed.
+                let _ = sender.send(path);
+            }
+        })?;
+
+        let directories = files
+            .iter()
+            .filter_map(|f| f.parent())
+            .collect::<BTreeSet<_>>();
+        for directory in directories {
+            debug!("Watching `{}` for shader changes.", directory.display());
+            watcher.watch(directory, RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(Self {
+            watcher,
+            changes: Mutex::new(changes),
+        })
+    }
+
+    /// The watched files that changed since the last call.
+    pub fn changed(&self) -> BTreeSet<PathBuf> {
+        self.changes.lock().unwrap().try_iter().collect()
+    }
+}
+
+/// An absolute path to `path` with its directory canonicalized, which is
+/// how the watcher reports events.
+fn absolute_path(path: &Path) -> Result<PathBuf> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+        _ => std::env::current_dir()?.canonicalize()?,
+    };
+
+    Ok(match path.file_name() {
+        Some(name) => directory.join(name),
+        None => directory,
+    })
+}
@@ -0,0 +1,198 @@
+//! Golden-image tests. Each scene is rendered by a headless `App` on Mesa
+//! lavapipe (or the device named by `VK_TUTORIAL_DEVICE`), captured to PNG and
+//! compared with the reference in `tests/golden`.
+//!
+//! The tests need a Vulkan driver, so they are ignored by default. Run them
+//! with `cargo test --test golden -- --ignored`; a missing loader is then a
+//! failure rather than a skip.
+//!
+//! Run with `UPDATE_GOLDEN=1` to overwrite the references with the current
+//! output. On a mismatch the captured frame and a diff image are written next
+//! to the other test artifacts under `target/tmp/golden`.
+
+use anyhow::{anyhow, ensure, Result};
+use std::{
+    env,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+use vk_tutorial::{
+    app::App,
+    capture,
+    config::{AppConfig, DeviceSelector, DEVICE_ENV},
+    loader,
+    pipeline::ShaderSource,
+};
+
+/// The largest per-channel difference accepted for a pixel to match.
+const TOLERANCE: u8 = 2;
+
+struct Scene {
+    name: &'static str,
+    size: (u32, u32),
+    clear_color: [f32; 4],
+    /// A WGSL file holding both shader stages of a pipeline that draws three
+    /// vertices, relative to the crate root.
+    shaders: Option<&'static str>,
+}
+
+struct Png {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", name))
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden")
+}
+
+fn render(scene: &Scene, path: &Path) -> Result<()> {
+    let device = env::var(DEVICE_ENV).unwrap_or_else(|_| "llvmpipe".into());
+    let shader = scene
+        .shaders
+        .map(|s| ShaderSource::Path(Path::new(env!("CARGO_MANIFEST_DIR")).join(s)));
+    let config = AppConfig {
+        device: Some(DeviceSelector::parse(&device)),
+        headless: true,
+        size: scene.size,
+        clear_color: scene.clear_color,
+        vertex_shader: shader.clone(),
+        fragment_shader: shader,
+        pipeline_cache: None,
+        ..Default::default()
+    };
+
+    unsafe {
+        let mut app = App::create_headless(&config)?;
+        let result = app.render_offscreen().and_then(|_| app.capture_frame(path));
+        app.destroy();
+        Ok(result?)
+    }
+}
+
+fn read_png(path: &Path) -> Result<Png> {
+    let decoder = png::Decoder::new(File::open(path)?);
+    let mut reader = decoder.read_info()?;
+    let mut rgba = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut rgba)?;
+
+    ensure!(
+        info.color_type == png::ColorType::Rgba && info.bit_depth == png::BitDepth::Eight,
+        "`{}` is not an RGBA8 image.",
+        path.display()
+    );
+
+    rgba.truncate(info.buffer_size());
+
+    Ok(Png {
+        width: info.width,
+        height: info.height,
+        rgba,
+    })
+}
+
+/// Returns the number of pixels that differ by more than `TOLERANCE` in any
+/// channel along with an image highlighting them in red.
+fn diff(expected: &Png, actual: &Png) -> (usize, Vec<u8>) {
+    let mut mismatches = 0;
+    let image = expected
+        .rgba
+        .chunks_exact(4)
+        .zip(actual.rgba.chunks_exact(4))
+        .flat_map(|(e, a)| {
+            if e.iter().zip(a).any(|(e, a)| e.abs_diff(*a) > TOLERANCE) {
+                mismatches += 1;
+                [255, 0, 0, 255]
+            } else {
+                let luma = ((e[0] as u32 + e[1] as u32 + e[2] as u32) / 12) as u8;
+                [luma, luma, luma, 255]
+            }
+        })
+        .collect();
+
+    (mismatches, image)
+}
+
+fn check_scene(scene: &Scene) -> Result<()> {
+    unsafe { loader::load_entry(None) }
+        .map_err(|e| anyhow!("The golden tests need a Vulkan loader: {}", e))?;
+
+    let output_dir = output_dir();
+    fs::create_dir_all(&output_dir)?;
+
+    let actual_path = output_dir.join(format!("{}.png", scene.name));
+    render(scene, &actual_path)?;
+
+    let golden_path = golden_path(scene.name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::copy(&actual_path, &golden_path)?;
+        return Ok(());
+    }
+
+    let expected = read_png(&golden_path)?;
+    let actual = read_png(&actual_path)?;
+
+    ensure!(
+        (expected.width, expected.height) == (actual.width, actual.height),
+        "`{}` is {}x{} but the golden image is {}x{}.",
+        scene.name,
+        actual.width,
+        actual.height,
+        expected.width,
+        expected.height
+    );
+
+    let (mismatches, image) = diff(&expected, &actual);
+    if mismatches > 0 {
+        let diff_path = output_dir.join(format!("{}-diff.png", scene.name));
+        capture::write_png(&diff_path, actual.width, actual.height, &image)?;
+
+        return Err(anyhow!(
+            "`{}` differs from its golden image in {} pixels (see `{}`).",
+            scene.name,
+            mismatches,
+            diff_path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs a Vulkan driver"]
+fn clear_black() -> Result<()> {
+    check_scene(&Scene {
+        name: "clear_black",
+        size: (64, 64),
+        clear_color: [0.0, 0.0, 0.0, 1.0],
+        shaders: None,
+    })
+}
+
+#[test]
+#[ignore = "needs a Vulkan driver"]
+fn clear_color() -> Result<()> {
+    check_scene(&Scene {
+        name: "clear_color",
+        size: (96, 64),
+        clear_color: [0.2, 0.4, 0.6, 1.0],
+        shaders: None,
+    })
+}
+
+#[test]
+#[ignore = "needs a Vulkan driver"]
+fn triangle() -> Result<()> {
+    check_scene(&Scene {
+        name: "triangle",
+        size: (64, 64),
+        clear_color: [0.0, 0.0, 0.0, 1.0],
+        shaders: Some("shaders/triangle.wgsl"),
+    })
+}